@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use fluidsim::fluid::{Cell, Fluid, Real, StepTimings, Vec2, Vec3};
+
+const SIZES: [usize; 4] = [64, 128, 256, 512];
+const STAGE_SIZE: usize = 256;
+/// Velocity grid under `DETAIL_SIZE` dye, against a plain `DETAIL_SIZE` fluid.
+const DETAIL_VELOCITY_SIZE: usize = 64;
+const DETAIL_SIZE: usize = 256;
+
+type Stage = fn(&StepTimings) -> Duration;
+
+fn vortex(size: usize) -> Fluid {
+    let mut fluid = Fluid::new(0.0001, 0.0001, size);
+    for ((x, y), cell) in fluid.cells.indexed_iter_mut() {
+        let position = Vec2::new(x as Real, y as Real) / size as Real - 0.5;
+        cell.dye = Vec3::splat((1.0 - position.length() * 4.0).max(0.0));
+        cell.velocity = position.perp();
+    }
+    fluid
+}
+
+fn delta() -> Duration {
+    Duration::from_secs_f32(1.0 / 60.0)
+}
+
+fn step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("step");
+    for size in SIZES {
+        let mut fluid = vortex(size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter(|| fluid.step(delta()))
+        });
+    }
+    group.finish();
+}
+
+// The stages are private, so time them through `step_profiled` and only
+// count the stage being measured.
+fn stages(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("stages {STAGE_SIZE}"));
+    let stages: [(&str, Stage); 3] = [
+        ("diffuse", |timings| timings.diffuse),
+        ("project", |timings| timings.project),
+        ("advect", |timings| timings.advect),
+    ];
+    for (name, stage) in stages {
+        let mut fluid = vortex(STAGE_SIZE);
+        group.bench_function(name, |b| {
+            b.iter_custom(|iterations| {
+                (0..iterations)
+                    .map(|_| stage(&fluid.step_profiled(delta())))
+                    .sum()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn detail(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("detail {DETAIL_SIZE}"));
+    let mut plain = vortex(DETAIL_SIZE);
+    group.bench_function("plain", |b| b.iter(|| plain.step(delta())));
+    let mut detailed = Fluid::with_detail(
+        0.0001,
+        0.0001,
+        (DETAIL_VELOCITY_SIZE, DETAIL_VELOCITY_SIZE),
+        (DETAIL_SIZE, DETAIL_SIZE),
+    )
+    .unwrap();
+    // The same vortex, with its dye drawn at the finer resolution.
+    detailed.init_with(|x, y| {
+        let position = Vec2::new(x, y) - 0.5;
+        Cell {
+            dye: Vec3::splat((1.0 - position.length() * 4.0).max(0.0)),
+            velocity: position.perp(),
+            ..Cell::default()
+        }
+    });
+    group.bench_function(format!("velocity {DETAIL_VELOCITY_SIZE}"), |b| {
+        b.iter(|| detailed.step(delta()))
+    });
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = step, stages, detail
+}
+criterion_main!(benches);
@@ -0,0 +1,81 @@
+use std::time::Instant;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use fluidsim::renderer::{TextureUpload, TextureUploader};
+use wgpu::*;
+
+const SIZE: u32 = 512;
+
+/// Any adapter will do, without a window. `WGPU_BACKEND` picks the backend,
+/// and `None` skips the benchmark on machines without an adapter.
+fn device() -> Option<(Device, Queue)> {
+    let instance = Instance::new(InstanceDescriptor {
+        backends: util::backend_bits_from_env().unwrap_or_else(Backends::all),
+        ..Default::default()
+    });
+    let adapter = futures::executor::block_on(instance.request_adapter(&Default::default()))?;
+    let info = adapter.get_info();
+    eprintln!("adapter: {} ({:?})", info.name, info.backend);
+    let limits = Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits());
+    futures::executor::block_on(adapter.request_device(
+        &DeviceDescriptor {
+            label: None,
+            features: Features::empty(),
+            limits,
+        },
+        None,
+    ))
+    .ok()
+}
+
+// Like a frame: upload, then submit so `write_texture` is flushed too. The
+// wait at the end counts the copies that are still in flight.
+fn upload(c: &mut Criterion) {
+    let Some((device, queue)) = device() else {
+        eprintln!("no adapter, skipping the upload benchmark");
+        return;
+    };
+    let texture = device.create_texture(&TextureDescriptor {
+        label: None,
+        size: Extent3d {
+            width: SIZE,
+            height: SIZE,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba8Unorm,
+        usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    let texels: Vec<[u8; 4]> = (0..SIZE * SIZE).map(|index| index.to_le_bytes()).collect();
+
+    let mut group = c.benchmark_group(format!("upload {SIZE}"));
+    for method in [TextureUpload::WriteTexture, TextureUpload::MappedBuffer] {
+        let uploader = TextureUploader::default();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{method:?}")),
+            &method,
+            |b, &method| {
+                b.iter_custom(|iterations| {
+                    let start = Instant::now();
+                    for _ in 0..iterations {
+                        uploader.upload(&device, &queue, &texture, &texels, method);
+                        queue.submit([]);
+                    }
+                    device.poll(Maintain::Wait);
+                    start.elapsed()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = upload
+}
+criterion_main!(benches);
@@ -0,0 +1,72 @@
+use glam::Vec3;
+
+pub const LUT_SIZE: usize = 256;
+pub const OBSTACLE_COLOR: [u8; 4] = [96, 96, 96, u8::MAX];
+/// What the fastest smoke turns in `RenderMode::SpeedTint`.
+pub const SPEED_TINT: Vec3 = Vec3::new(1.0, 0.6, 0.2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Colormap {
+    #[default]
+    Dye,
+    Grayscale,
+    Viridis,
+    Inferno,
+}
+
+impl Colormap {
+    pub fn next(self) -> Self {
+        match self {
+            Colormap::Dye => Colormap::Grayscale,
+            Colormap::Grayscale => Colormap::Viridis,
+            Colormap::Viridis => Colormap::Inferno,
+            Colormap::Inferno => Colormap::Dye,
+        }
+    }
+
+    pub fn sample(self, t: f32) -> Vec3 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Colormap::Dye | Colormap::Grayscale => Vec3::splat(t),
+            Colormap::Viridis => polynomial(&VIRIDIS, t),
+            Colormap::Inferno => polynomial(&INFERNO, t),
+        }
+    }
+
+    pub fn lut(self) -> Vec<[u8; 4]> {
+        (0..LUT_SIZE)
+            .map(|i| {
+                let color = self.sample(i as f32 / (LUT_SIZE - 1) as f32) * u8::MAX as f32;
+                [color.x as u8, color.y as u8, color.z as u8, u8::MAX]
+            })
+            .collect()
+    }
+}
+
+fn polynomial(coefficients: &[Vec3; 7], t: f32) -> Vec3 {
+    let color = coefficients
+        .iter()
+        .rev()
+        .fold(Vec3::ZERO, |acc, &coefficient| acc * t + coefficient);
+    color.clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+const VIRIDIS: [Vec3; 7] = [
+    Vec3::new(0.277_727_33, 0.005_407_344_5, 0.334_099_8),
+    Vec3::new(0.105_093_04, 1.404_613_5, 1.384_590_2),
+    Vec3::new(-0.330_861_83, 0.214_847_56, 0.095_095_16),
+    Vec3::new(-4.634_230_4, -5.799_101, -19.332_441),
+    Vec3::new(6.228_27, 14.179_933, 56.690_55),
+    Vec3::new(4.776_385, -13.745_146, -65.353_03),
+    Vec3::new(-5.435_456, 4.645_852_6, 26.312_435),
+];
+
+const INFERNO: [Vec3; 7] = [
+    Vec3::new(0.000_218_940_37, 0.001_651_004_6, -0.019_480_898),
+    Vec3::new(0.106_513_42, 0.563_956_4, 3.932_712_4),
+    Vec3::new(11.602_493, -3.972_854, -15.942_394),
+    Vec3::new(-41.703_995, 17.436_398, 44.354_145),
+    Vec3::new(77.162_94, -33.402_36, -81.807_31),
+    Vec3::new(-71.319_43, 32.626_064, 73.209_52),
+    Vec3::new(25.131_126, -12.242_669, -23.070_325),
+];
@@ -0,0 +1,80 @@
+use std::{
+    mem,
+    ops::{Deref, DerefMut},
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A value and the one it replaced, for stages that read the last state
+/// while writing the next. Derefs to the current value.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DoubleBuffer<T> {
+    current: T,
+    previous: T,
+}
+
+impl<T> DoubleBuffer<T> {
+    pub fn new(current: T, previous: T) -> Self {
+        Self { current, previous }
+    }
+
+    pub fn current(&self) -> &T {
+        &self.current
+    }
+
+    pub fn current_mut(&mut self) -> &mut T {
+        &mut self.current
+    }
+
+    pub fn previous(&self) -> &T {
+        &self.previous
+    }
+
+    pub fn previous_mut(&mut self) -> &mut T {
+        &mut self.previous
+    }
+
+    /// Makes the current value the previous one. The new current value is
+    /// the old previous one, ready to be overwritten.
+    pub fn swap(&mut self) {
+        mem::swap(&mut self.current, &mut self.previous);
+    }
+
+    /// Borrows the current value mutably and the previous one alongside it.
+    pub fn split_mut(&mut self) -> (&mut T, &T) {
+        (&mut self.current, &self.previous)
+    }
+
+    pub fn into_current(self) -> T {
+        self.current
+    }
+}
+
+impl<T: Clone> DoubleBuffer<T> {
+    /// Both values start out as `value`.
+    pub fn splat(value: T) -> Self {
+        Self::new(value.clone(), value)
+    }
+
+    /// Replaces both values with `value`.
+    pub fn set(&mut self, value: T) {
+        self.previous = value.clone();
+        self.current = value;
+    }
+}
+
+impl<T> Deref for DoubleBuffer<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.current
+    }
+}
+
+impl<T> DerefMut for DoubleBuffer<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.current
+    }
+}
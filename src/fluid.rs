@@ -1,179 +1,2522 @@
+#[cfg(feature = "serde")]
+use std::io::Read;
+use std::io::Write;
+#[cfg(feature = "image")]
+use std::path::Path;
 use std::{
+    error::Error,
+    fmt::Display,
     mem,
-    ops::{Index, IndexMut},
+    ops::{Add, AddAssign, Index, IndexMut, Mul, Sub},
     time::Duration,
 };
 
-use glam::Vec2;
-use ndarray::Array2;
+#[cfg(feature = "f64")]
+pub use glam::{DVec2 as Vec2, DVec3 as Vec3};
+#[cfg(not(feature = "f64"))]
+pub use glam::{Vec2, Vec3};
+use ndarray::{Array2, Zip};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, Default)]
+#[cfg(feature = "image")]
+use crate::colormap::{Colormap, OBSTACLE_COLOR};
+use crate::{double_buffer::DoubleBuffer, timer::Instant};
+
+/// The float type the simulation runs in, `f64` with the `f64` feature.
+/// `Vec2` and `Vec3` are re-exported from glam at the same precision.
+#[cfg(not(feature = "f64"))]
+pub type Real = f32;
+#[cfg(feature = "f64")]
+pub type Real = f64;
+
+const MAX_SUBSTEPS: usize = 64;
+const DEFAULT_MAX_DT: Duration = Duration::from_micros(33_333);
+pub const DEFAULT_FIXED_DT: Duration = Duration::from_micros(16_667);
+const DEFAULT_ITERATIONS: usize = 20;
+const DEFAULT_SIZE: usize = 128;
+const MULTIGRID_SWEEPS: usize = 2;
+const MULTIGRID_COARSE_SWEEPS: usize = 20;
+const MULTIGRID_MIN_SIZE: usize = 4;
+const VORTEX_RADIUS: Real = 0.25;
+const VORTEX_SPEED: Real = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Cell {
-    pub density: f32,
+    pub dye: Vec3,
+    pub velocity: Vec2,
+    pub temperature: Real,
+}
+
+impl Cell {
+    pub fn is_finite(&self) -> bool {
+        self.dye.is_finite() && self.velocity.is_finite() && self.temperature.is_finite()
+    }
+
+    pub fn density(&self) -> Real {
+        (self.dye.x + self.dye.y + self.dye.z) / 3.0
+    }
+
+    pub fn lerp(a: Cell, b: Cell, t: Real) -> Cell {
+        a * (1.0 - t) + b * t
+    }
+
+    /// Bilinear interpolation between `[top_left, top_right, bottom_left,
+    /// bottom_right]`, with `t.x` running left to right and `t.y` top to
+    /// bottom.
+    pub fn bilerp(corners: [Cell; 4], t: Vec2) -> Cell {
+        let [top_left, top_right, bottom_left, bottom_right] = corners;
+        Cell::lerp(
+            Cell::lerp(top_left, bottom_left, t.y),
+            Cell::lerp(top_right, bottom_right, t.y),
+            t.x,
+        )
+    }
+}
+
+// Field by field, so solver code can combine whole cells.
+impl Add for Cell {
+    type Output = Cell;
+
+    fn add(self, other: Cell) -> Cell {
+        Cell {
+            dye: self.dye + other.dye,
+            velocity: self.velocity + other.velocity,
+            temperature: self.temperature + other.temperature,
+        }
+    }
+}
+
+impl AddAssign for Cell {
+    fn add_assign(&mut self, other: Cell) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Cell {
+    type Output = Cell;
+
+    fn sub(self, other: Cell) -> Cell {
+        Cell {
+            dye: self.dye - other.dye,
+            velocity: self.velocity - other.velocity,
+            temperature: self.temperature - other.temperature,
+        }
+    }
+}
+
+impl Mul<Real> for Cell {
+    type Output = Cell;
+
+    fn mul(self, factor: Real) -> Cell {
+        Cell {
+            dye: self.dye * factor,
+            velocity: self.velocity * factor,
+            temperature: self.temperature * factor,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BoundaryMode {
+    #[default]
+    Wrap,
+    Reflect,
+    Open,
+}
+
+impl BoundaryMode {
+    pub fn next(self) -> Self {
+        match self {
+            BoundaryMode::Wrap => BoundaryMode::Reflect,
+            BoundaryMode::Reflect => BoundaryMode::Open,
+            BoundaryMode::Open => BoundaryMode::Wrap,
+        }
+    }
+
+    fn resolve_index(self, index: isize, size: usize) -> usize {
+        match self {
+            BoundaryMode::Wrap => wrap_index(index, size),
+            BoundaryMode::Reflect | BoundaryMode::Open => clamp_index(index, size),
+        }
+    }
+}
+
+/// A `BoundaryMode` for each axis: `x` for the left and right edges, `y`
+/// for the top and bottom. A bare `BoundaryMode` converts to the same mode on
+/// both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Boundary {
+    pub x: BoundaryMode,
+    pub y: BoundaryMode,
+}
+
+impl Boundary {
+    pub fn new(x: BoundaryMode, y: BoundaryMode) -> Self {
+        Self { x, y }
+    }
+
+    pub fn wraps(self) -> bool {
+        self.x == BoundaryMode::Wrap && self.y == BoundaryMode::Wrap
+    }
+
+    fn resolve(self, i: isize, j: isize, (width, height): (usize, usize)) -> (usize, usize) {
+        (
+            self.x.resolve_index(i, width),
+            self.y.resolve_index(j, height),
+        )
+    }
+}
+
+impl From<BoundaryMode> for Boundary {
+    fn from(mode: BoundaryMode) -> Self {
+        Self::new(mode, mode)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Edge {
+    Left,
+    Right,
+    Bottom,
+    Top,
+}
+
+/// Fluid entering the grid through one edge, see [`Fluid::set_inflow`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Inflow {
+    pub edge: Edge,
+    pub velocity: Vec2,
+    pub density: Real,
+}
+
+/// How dye and temperature behave at the edges of a non-wrapping grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ScalarBoundary {
+    /// Zero gradient: the edge cells copy their inner neighbours, so smoke
+    /// collects against the walls.
+    #[default]
+    Neumann,
+    /// Zero value: the edge cells are cleared, so the walls absorb smoke.
+    Dirichlet,
+}
+
+/// What `step` does when it leaves a NaN or infinity in a cell, which
+/// otherwise spreads until the whole grid is blank.
+///
+/// Checking scans every cell after each step, so it's off unless asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NanGuard {
+    /// Don't check.
+    #[default]
+    Off,
+    /// Panic with the first bad cell's coordinates.
+    Panic,
+    /// Reset bad cells to `Cell::default()`.
+    Reset,
+}
+
+/// A source applied at the start of every step, see [`Fluid::add_emitter`].
+/// `position` and `radius` are in the same units as [`Fluid::splat`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Emitter {
+    pub position: Vec2,
+    pub radius: Real,
+    /// Gray dye added per second at the center.
+    pub density_rate: Real,
+    /// Heat added per second at the center, which rises with
+    /// `Fluid::buoyancy_beta`.
+    pub temperature_rate: Real,
+    /// Velocity the fluid at the center is held at.
+    pub velocity: Vec2,
+}
+
+/// How a [`VelocityRegion`] drives the cells it covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RegionMode {
+    /// Overwrite the velocity, like a conveyor.
+    #[default]
+    Set,
+    /// Accelerate by the velocity every second, like a fan.
+    Add,
+}
+
+/// A box of cells driven every substep, see [`Fluid::add_velocity_region`].
+/// `min` and `max` are inclusive cell indices, clipped to the grid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VelocityRegion {
+    pub min: (isize, isize),
+    pub max: (isize, isize),
     pub velocity: Vec2,
+    pub mode: RegionMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AdvectionScheme {
+    #[default]
+    SemiLagrangian,
+    MacCormack,
+    /// Back and forth error compensation: advects forward, back, and forward
+    /// again from a corrected field, so it costs three passes. `clamp` limits
+    /// each cell to the values it was interpolated from, which stops
+    /// overshoot at sharp edges.
+    Bfecc {
+        clamp: bool,
+    },
+}
+
+/// How advection traces each cell back along the velocity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BacktraceOrder {
+    /// One Euler step with the cell's own velocity.
+    #[default]
+    Rk1,
+    /// A midpoint step: half a step back, then the full step with the
+    /// velocity found there. Follows curved flow much more closely.
+    Rk2,
+}
+
+/// Weight profile used by [`Fluid::splat`], evaluated on the distance from
+/// the splat center divided by its radius.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Falloff {
+    Hard,
+    #[default]
+    Gaussian,
+    Smoothstep,
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct Fluid {
-    pub diffusion: f32,
-    pub viscosity: f32,
-    pub size: usize,
-    pub cells: Array2<Cell>,
-    pub prev_cells: Array2<Cell>,
-}
+impl Falloff {
+    pub fn weight(self, distance: Real) -> Real {
+        if distance >= 1.0 {
+            return 0.0;
+        }
+        match self {
+            Falloff::Hard => 1.0,
+            Falloff::Gaussian => (-4.0 * distance * distance).exp(),
+            Falloff::Smoothstep => {
+                let t = 1.0 - distance;
+                t * t * (3.0 - 2.0 * t)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PressureSolver {
+    /// A fixed number of red-black Gauss-Seidel sweeps.
+    GaussSeidel { iterations: usize },
+    /// Conjugate gradient, stopping once the residual norm drops below
+    /// `tolerance` relative to the divergence norm.
+    ConjugateGradient {
+        tolerance: Real,
+        max_iterations: usize,
+    },
+    /// `cycles` geometric multigrid V-cycles over at most `levels` grids,
+    /// each half the size of the one above.
+    Multigrid { levels: usize, cycles: usize },
+}
+
+impl PressureSolver {
+    /// The most iterations the solver will run, counting V-cycles for
+    /// `Multigrid`.
+    pub fn max_iterations(self) -> usize {
+        match self {
+            PressureSolver::GaussSeidel { iterations } => iterations,
+            PressureSolver::ConjugateGradient { max_iterations, .. } => max_iterations,
+            PressureSolver::Multigrid { cycles, .. } => cycles,
+        }
+    }
+}
+
+impl Default for PressureSolver {
+    fn default() -> Self {
+        PressureSolver::GaussSeidel {
+            iterations: DEFAULT_ITERATIONS,
+        }
+    }
+}
+
+/// Time spent in each stage of a step, summed over substeps. `total` also
+/// covers forces, vorticity confinement and dissipation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StepTimings {
+    pub diffuse: Duration,
+    pub project: Duration,
+    pub advect: Duration,
+    pub total: Duration,
+}
+
+/// Solves the pressure Poisson equation during projection.
+///
+/// `solve` is called with `fluid.divergence` filled in and `fluid.pressure`
+/// holding the initial guess: zero, or the previous solution when
+/// `Fluid::warm_start` is on. It must leave the solution in `fluid.pressure`.
+pub trait PressureBackend {
+    fn solve(&mut self, fluid: &mut Fluid);
+
+    /// Takes over the whole projection, for backends that keep the velocity
+    /// themselves: remove the divergence from the cells' velocity, leave the
+    /// pressure in `fluid.pressure` and return `true`. The default returns
+    /// `false`, and `project` computes the divergence, calls `solve` and
+    /// subtracts the pressure gradient on the CPU.
+    fn project(&mut self, _fluid: &mut Fluid) -> bool {
+        false
+    }
+}
+
+/// Runs `Fluid::pressure_solver` on the CPU, used by `step` and `advance`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuPressure;
+
+impl PressureBackend for CpuPressure {
+    fn solve(&mut self, fluid: &mut Fluid) {
+        match fluid.pressure_solver {
+            PressureSolver::GaussSeidel { iterations } => fluid.relax_pressure(iterations),
+            PressureSolver::ConjugateGradient {
+                tolerance,
+                max_iterations,
+            } => fluid.conjugate_gradient(tolerance, max_iterations),
+            PressureSolver::Multigrid { levels, cycles } => fluid.multigrid(levels, cycles),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BoundaryField {
+    Dye,
+    Temperature,
+    VelocityX,
+    VelocityY,
+    Divergence,
+    Pressure,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Fluid {
+    pub diffusion: Real,
+    pub viscosity: Real,
+    pub vorticity: Real,
+    pub gravity: Vec2,
+    /// Buoyancy adds `(buoyancy_beta * temperature - buoyancy_alpha * density)`
+    /// upwards (+y) to the velocity: heat lifts smoke, its weight pulls it down.
+    pub buoyancy_alpha: Real,
+    pub buoyancy_beta: Real,
+    pub density_decay: Real,
+    pub velocity_damping: Real,
+    pub solver_iterations: usize,
+    pub pressure_solver: PressureSolver,
+    pub adaptive_substeps: bool,
+    /// Starts each of the two pressure solves in a substep from its solution
+    /// in the last substep instead of zero, which converges in fewer
+    /// iterations when the flow changes slowly. Turn it off to make every
+    /// solve independent of the ones before.
+    pub warm_start: bool,
+    pub nan_guard: NanGuard,
+    /// Off, dye and temperature keep sharp edges like ink while velocity
+    /// still diffuses with `viscosity`.
+    pub diffuse_density: bool,
+    /// Rescales the dye after each advection so its total is what it was
+    /// before, undoing the mass interpolation gains or loses. Only meaningful
+    /// when the boundaries are closed; with `BoundaryMode::Open` it would put
+    /// back the dye that left.
+    pub conserve_density: bool,
+    /// Lets density carry momentum: each cell has mass `1 + density`, which
+    /// divides the forces applied to it, and advection transports momentum
+    /// instead of velocity. Off, density is a passive scalar.
+    pub variable_density: bool,
+    /// Upper bound on the delta accepted by `step`; a stalled frame would otherwise
+    /// backtrace far past neighbouring cells and blow up the solve. Not applied
+    /// by `step_substeps`.
+    pub max_dt: Duration,
+    /// Timestep used by `advance`.
+    pub fixed_dt: Duration,
+    pub boundary: Boundary,
+    /// Ignored on axes with `BoundaryMode::Wrap`, which have no edges.
+    pub density_boundary: ScalarBoundary,
+    pub advection: AdvectionScheme,
+    pub backtrace: BacktraceOrder,
+    pub width: usize,
+    pub height: usize,
+    /// The current cells, with the last stage's input kept as the previous
+    /// buffer.
+    pub cells: DoubleBuffer<Array2<Cell>>,
+    pub divergence: Array2<Real>,
+    pub pressure: Array2<Real>,
+    pub obstacles: Array2<bool>,
+    pub inflows: Vec<Inflow>,
+    pub emitters: Vec<Emitter>,
+    pub velocity_regions: Vec<VelocityRegion>,
+    /// A target velocity and the rate per second at which every cell relaxes
+    /// toward it, see [`Fluid::set_ambient_flow`].
+    pub ambient_flow: Option<(Vec2, Real)>,
+    /// Dye on a finer grid than the cells, see [`Fluid::with_detail`]. Each
+    /// cell's dye is kept at the average of the detail it covers, so
+    /// buoyancy and everything else that reads density works as before.
+    pub detail: Option<DyeDetail>,
+    previous_pressure: Array2<Real>,
+    forces: Array2<Vec2>,
+    accumulator: Duration,
+}
+
+/// Dye on a grid `factor` times finer than the cells on both axes, with the
+/// last advection's input kept as the previous buffer like `Fluid::cells`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DyeDetail {
+    pub factor: usize,
+    pub dye: DoubleBuffer<Array2<Vec3>>,
+}
+
+impl DyeDetail {
+    /// The average dye over each cell's `factor` by `factor` block.
+    fn block_means(&self, (width, height): (usize, usize)) -> Array2<Vec3> {
+        let factor = self.factor;
+        let weight = 1.0 / (factor * factor) as Real;
+        Array2::from_shape_fn((width, height), |(x, y)| {
+            let mut sum = Vec3::ZERO;
+            for i in x * factor..(x + 1) * factor {
+                for j in y * factor..(y + 1) * factor {
+                    sum += self.dye[[i, j]];
+                }
+            }
+            sum * weight
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FluidBuilder {
+    diffusion: Real,
+    viscosity: Real,
+    width: usize,
+    height: usize,
+    boundary: Boundary,
+    density_boundary: ScalarBoundary,
+    advection: AdvectionScheme,
+    backtrace: BacktraceOrder,
+    vorticity: Real,
+    gravity: Vec2,
+    buoyancy_alpha: Real,
+    buoyancy_beta: Real,
+    solver_iterations: usize,
+    pressure_solver: PressureSolver,
+    variable_density: bool,
+    warm_start: bool,
+    diffuse_density: bool,
+    conserve_density: bool,
+    fixed_dt: Duration,
+    nan_guard: NanGuard,
+}
+
+impl Default for FluidBuilder {
+    fn default() -> Self {
+        Self {
+            diffusion: 0.0,
+            viscosity: 0.0,
+            width: DEFAULT_SIZE,
+            height: DEFAULT_SIZE,
+            boundary: Boundary::default(),
+            density_boundary: ScalarBoundary::default(),
+            advection: AdvectionScheme::SemiLagrangian,
+            backtrace: BacktraceOrder::default(),
+            vorticity: 0.0,
+            gravity: Vec2::ZERO,
+            buoyancy_alpha: 0.0,
+            buoyancy_beta: 0.0,
+            solver_iterations: DEFAULT_ITERATIONS,
+            pressure_solver: PressureSolver::default(),
+            variable_density: false,
+            warm_start: true,
+            diffuse_density: true,
+            conserve_density: false,
+            fixed_dt: DEFAULT_FIXED_DT,
+            nan_guard: NanGuard::default(),
+        }
+    }
+}
+
+impl FluidBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn diffusion(mut self, diffusion: Real) -> Self {
+        self.diffusion = diffusion;
+        self
+    }
+
+    pub fn viscosity(mut self, viscosity: Real) -> Self {
+        self.viscosity = viscosity;
+        self
+    }
+
+    pub fn size(self, size: usize) -> Self {
+        self.dimensions(size, size)
+    }
+
+    pub fn dimensions(mut self, width: usize, height: usize) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn boundary(mut self, boundary: impl Into<Boundary>) -> Self {
+        self.boundary = boundary.into();
+        self
+    }
+
+    pub fn density_boundary(mut self, density_boundary: ScalarBoundary) -> Self {
+        self.density_boundary = density_boundary;
+        self
+    }
+
+    pub fn advection(mut self, advection: AdvectionScheme) -> Self {
+        self.advection = advection;
+        self
+    }
+
+    pub fn backtrace(mut self, backtrace: BacktraceOrder) -> Self {
+        self.backtrace = backtrace;
+        self
+    }
+
+    pub fn vorticity(mut self, vorticity: Real) -> Self {
+        self.vorticity = vorticity;
+        self
+    }
+
+    pub fn gravity(mut self, gravity: Vec2) -> Self {
+        self.gravity = gravity;
+        self
+    }
+
+    pub fn buoyancy(mut self, alpha: Real, beta: Real) -> Self {
+        self.buoyancy_alpha = alpha;
+        self.buoyancy_beta = beta;
+        self
+    }
+
+    pub fn solver_iterations(mut self, iterations: usize) -> Self {
+        self.solver_iterations = iterations;
+        self
+    }
+
+    pub fn pressure_iterations(self, iterations: usize) -> Self {
+        self.pressure_solver(PressureSolver::GaussSeidel { iterations })
+    }
+
+    pub fn pressure_solver(mut self, solver: PressureSolver) -> Self {
+        self.pressure_solver = solver;
+        self
+    }
+
+    pub fn variable_density(mut self, variable_density: bool) -> Self {
+        self.variable_density = variable_density;
+        self
+    }
+
+    pub fn warm_start(mut self, warm_start: bool) -> Self {
+        self.warm_start = warm_start;
+        self
+    }
+
+    pub fn diffuse_density(mut self, diffuse_density: bool) -> Self {
+        self.diffuse_density = diffuse_density;
+        self
+    }
+
+    pub fn conserve_density(mut self, conserve_density: bool) -> Self {
+        self.conserve_density = conserve_density;
+        self
+    }
+
+    pub fn fixed_dt(mut self, fixed_dt: Duration) -> Self {
+        self.fixed_dt = fixed_dt;
+        self
+    }
+
+    pub fn nan_guard(mut self, nan_guard: NanGuard) -> Self {
+        self.nan_guard = nan_guard;
+        self
+    }
+
+    pub fn build(self) -> Fluid {
+        let mut fluid =
+            Fluid::with_dimensions(self.diffusion, self.viscosity, self.width, self.height);
+        fluid.boundary = self.boundary;
+        fluid.density_boundary = self.density_boundary;
+        fluid.advection = self.advection;
+        fluid.backtrace = self.backtrace;
+        fluid.vorticity = self.vorticity;
+        fluid.gravity = self.gravity;
+        fluid.buoyancy_alpha = self.buoyancy_alpha;
+        fluid.buoyancy_beta = self.buoyancy_beta;
+        fluid.solver_iterations = self.solver_iterations;
+        fluid.pressure_solver = self.pressure_solver;
+        fluid.variable_density = self.variable_density;
+        fluid.warm_start = self.warm_start;
+        fluid.diffuse_density = self.diffuse_density;
+        fluid.conserve_density = self.conserve_density;
+        fluid.fixed_dt = self.fixed_dt;
+        fluid.nan_guard = self.nan_guard;
+        fluid
+    }
+}
+
+impl Fluid {
+    pub fn builder() -> FluidBuilder {
+        FluidBuilder::new()
+    }
+
+    pub fn new(diffusion: Real, viscosity: Real, size: usize) -> Self {
+        Self::with_dimensions(diffusion, viscosity, size, size)
+    }
+
+    pub fn with_dimensions(diffusion: Real, viscosity: Real, width: usize, height: usize) -> Self {
+        Self {
+            diffusion,
+            viscosity,
+            vorticity: 0.0,
+            gravity: Vec2::ZERO,
+            buoyancy_alpha: 0.0,
+            buoyancy_beta: 0.0,
+            density_decay: 0.0,
+            velocity_damping: 0.0,
+            solver_iterations: DEFAULT_ITERATIONS,
+            pressure_solver: PressureSolver::default(),
+            adaptive_substeps: false,
+            warm_start: true,
+            nan_guard: NanGuard::default(),
+            diffuse_density: true,
+            conserve_density: false,
+            variable_density: false,
+            max_dt: DEFAULT_MAX_DT,
+            fixed_dt: DEFAULT_FIXED_DT,
+            boundary: Boundary::default(),
+            density_boundary: ScalarBoundary::default(),
+            advection: AdvectionScheme::SemiLagrangian,
+            backtrace: BacktraceOrder::default(),
+            width,
+            height,
+            cells: DoubleBuffer::splat(Array2::default((width, height))),
+            divergence: Array2::zeros((width, height)),
+            pressure: Array2::zeros((width, height)),
+            previous_pressure: Array2::zeros((width, height)),
+            obstacles: Array2::default((width, height)),
+            inflows: Vec::new(),
+            emitters: Vec::new(),
+            velocity_regions: Vec::new(),
+            ambient_flow: None,
+            detail: None,
+            forces: Array2::default((width, height)),
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    /// A fluid holding `cells`, sized to match them.
+    pub fn from_cells(cells: Array2<Cell>, diffusion: Real, viscosity: Real) -> Self {
+        let (width, height) = cells.dim();
+        let mut fluid = Self::with_dimensions(diffusion, viscosity, width, height);
+        fluid.cells.set(cells);
+        fluid
+    }
+
+    /// A `width` by `height` fluid that carries its dye on a finer
+    /// `density_width` by `density_height` grid, which has to be the same
+    /// whole multiple of the first on both axes. Velocity and pressure are
+    /// solved on the coarse grid alone; the dye is advected along the coarse
+    /// velocity interpolated bilinearly, always semi-Lagrangian, so it keeps
+    /// detail finer than the flow that moves it.
+    pub fn with_detail(
+        diffusion: Real,
+        viscosity: Real,
+        (width, height): (usize, usize),
+        (density_width, density_height): (usize, usize),
+    ) -> Result<Self, MismatchedResolution> {
+        let factor = density_width / width.max(1);
+        if factor == 0 || (width * factor, height * factor) != (density_width, density_height) {
+            return Err(MismatchedResolution {
+                velocity: (width, height),
+                density: (density_width, density_height),
+            });
+        }
+        let mut fluid = Self::with_dimensions(diffusion, viscosity, width, height);
+        fluid.detail = Some(DyeDetail {
+            factor,
+            dye: DoubleBuffer::splat(Array2::from_elem(
+                (density_width, density_height),
+                Vec3::ZERO,
+            )),
+        });
+        Ok(fluid)
+    }
+
+    pub fn into_cells(self) -> Array2<Cell> {
+        self.cells.into_current()
+    }
+
+    /// A `size` by `size` fluid with a single vortex spinning
+    /// counterclockwise around the center, its core marked with dye.
+    pub fn with_vortex(size: usize) -> Self {
+        let mut fluid = Fluid::builder().size(size).build();
+        fluid.init_with(|x, y| {
+            let offset = Vec2::new(x, y) - 0.5;
+            let weight = Falloff::Gaussian.weight(offset.length() / VORTEX_RADIUS);
+            Cell {
+                dye: Vec3::splat(weight),
+                velocity: weight * VORTEX_SPEED * offset.perp().normalize_or_zero(),
+                temperature: 0.0,
+            }
+        });
+        fluid
+    }
+
+    /// A `size` by `size` fluid at rest with a dye blob of `radius` around
+    /// `center`, both in normalized coordinates.
+    pub fn with_blob(size: usize, center: Vec2, radius: Real) -> Self {
+        let mut fluid = Fluid::builder().size(size).build();
+        fluid.init_with(|x, y| Cell {
+            dye: Vec3::splat(Falloff::Gaussian.weight(Vec2::new(x, y).distance(center) / radius)),
+            ..Cell::default()
+        });
+        fluid
+    }
+
+    /// Sets every fluid cell to `f(x, y)`, where `x` and `y` are the cell's
+    /// center in normalized coordinates from 0 to 1. Obstacle cells stay empty.
+    /// With `detail`, the dye is taken from `f` at every detail cell instead.
+    pub fn init_with(&mut self, f: impl Fn(Real, Real) -> Cell) {
+        let (width, height) = (self.width as Real, self.height as Real);
+        let obstacles = &self.obstacles;
+        self.cells.set(Array2::from_shape_fn(
+            (self.width, self.height),
+            |(x, y)| {
+                if obstacles[[x, y]] {
+                    Cell::default()
+                } else {
+                    f((x as Real + 0.5) / width, (y as Real + 0.5) / height)
+                }
+            },
+        ));
+
+        if let Some(detail) = &mut self.detail {
+            let factor = detail.factor;
+            let (width, height) = (width * factor as Real, height * factor as Real);
+            detail
+                .dye
+                .set(Array2::from_shape_fn(detail.dye.dim(), |(x, y)| {
+                    if obstacles[[x / factor, y / factor]] {
+                        Vec3::ZERO
+                    } else {
+                        f((x as Real + 0.5) / width, (y as Real + 0.5) / height).dye
+                    }
+                }));
+            self.pull_detail();
+        }
+    }
+
+    /// Sets `diffusion`, clamping negative values to zero. Leaves it
+    /// unchanged if `diffusion` isn't finite.
+    pub fn set_diffusion(&mut self, diffusion: Real) -> Result<(), InvalidCoefficient> {
+        self.diffusion = validate_coefficient("diffusion", diffusion)?;
+        Ok(())
+    }
+
+    /// Sets `viscosity` like `set_diffusion`.
+    pub fn set_viscosity(&mut self, viscosity: Real) -> Result<(), InvalidCoefficient> {
+        self.viscosity = validate_coefficient("viscosity", viscosity)?;
+        Ok(())
+    }
+
+    pub fn add_force(&mut self, x: isize, y: isize, force: Vec2) {
+        let (x, y) = self.boundary.resolve(x, y, (self.width, self.height));
+        self.forces[[x, y]] += force;
+    }
+
+    pub fn splat(&mut self, center: Vec2, radius: Real, falloff: Falloff, amount: Cell) {
+        self.for_each_in_radius(center, radius, falloff, |cell, weight| {
+            *cell += amount * weight;
+        });
+    }
+
+    /// Perturbs the velocity of every fluid cell with curl noise, which swirls
+    /// the flow without adding divergence. `scale` is the size of the swirls
+    /// in the units of `splat`'s radius and `amplitude` scales the speed
+    /// added. The same `seed` always gives the same perturbation.
+    pub fn add_turbulence(&mut self, seed: u64, amplitude: Real, scale: Real) {
+        if amplitude == 0.0 || scale <= 0.0 {
+            return;
+        }
+
+        let cells_per_swirl = scale * self.scale();
+        Zip::indexed(self.cells.current_mut())
+            .and(&self.obstacles)
+            .for_each(|(x, y), cell, &solid| {
+                if solid {
+                    return;
+                }
+                let position = Vec2::new(x as Real + 0.5, y as Real + 0.5) / cells_per_swirl;
+                // The gradient of smoothstepped value noise is at most 3 along
+                // each axis.
+                cell.velocity += amplitude / 3.0 * noise_gradient(seed, position).perp();
+            });
+    }
+
+    /// Adds `emitter`, returning its index in `emitters`.
+    pub fn add_emitter(&mut self, emitter: Emitter) -> usize {
+        self.emitters.push(emitter);
+        self.emitters.len() - 1
+    }
+
+    pub fn remove_emitter(&mut self, index: usize) -> Emitter {
+        self.emitters.remove(index)
+    }
+
+    pub fn clear_emitters(&mut self) {
+        self.emitters.clear();
+    }
+
+    /// Nudges the velocity of every fluid cell toward `target` at the start
+    /// of each step by `(target - velocity) * strength * dt`, like a steady
+    /// breeze. Unlike gravity it doesn't accumulate past the target. `None`
+    /// turns it off.
+    pub fn set_ambient_flow(&mut self, flow: Option<(Vec2, Real)>) {
+        self.ambient_flow = flow;
+    }
+
+    fn apply_ambient_flow(&mut self, delta: Real) {
+        let Some((target, strength)) = self.ambient_flow else {
+            return;
+        };
+        // Past 1 a long step would overshoot the target.
+        let blend = (strength * delta).clamp(0.0, 1.0);
+        Zip::from(self.cells.current_mut())
+            .and(&self.obstacles)
+            .for_each(|cell, &solid| {
+                if !solid {
+                    cell.velocity += (target - cell.velocity) * blend;
+                }
+            });
+    }
+
+    /// Holds the cells from `min` to `max` inclusive at `velocity` every
+    /// substep, returning the region's index in `velocity_regions`. Push a
+    /// [`VelocityRegion`] directly to accelerate the cells instead.
+    pub fn add_velocity_region(
+        &mut self,
+        min: (isize, isize),
+        max: (isize, isize),
+        velocity: Vec2,
+    ) -> usize {
+        self.velocity_regions.push(VelocityRegion {
+            min,
+            max,
+            velocity,
+            mode: RegionMode::Set,
+        });
+        self.velocity_regions.len() - 1
+    }
+
+    pub fn remove_velocity_region(&mut self, index: usize) -> VelocityRegion {
+        self.velocity_regions.remove(index)
+    }
+
+    pub fn clear_velocity_regions(&mut self) {
+        self.velocity_regions.clear();
+    }
+
+    /// Calls `f` with every fluid cell within `radius` of `center` and its
+    /// `falloff` weight, as `splat` applies them.
+    fn for_each_in_radius(
+        &mut self,
+        center: Vec2,
+        radius: Real,
+        falloff: Falloff,
+        mut f: impl FnMut(&mut Cell, Real),
+    ) {
+        let scale = self.scale();
+        let center = center * Vec2::new(self.width as Real, self.height as Real);
+        let cell_radius = radius * scale;
+        let extent = cell_radius.ceil() as isize;
+        let (center_x, center_y) = (center.x.round() as isize, center.y.round() as isize);
+
+        for i in (center_x - extent)..=(center_x + extent) {
+            for j in (center_y - extent)..=(center_y + extent) {
+                let outside_x =
+                    !(0..self.width as isize).contains(&i) && self.boundary.x != BoundaryMode::Wrap;
+                let outside_y = !(0..self.height as isize).contains(&j)
+                    && self.boundary.y != BoundaryMode::Wrap;
+                if outside_x || outside_y {
+                    continue;
+                }
+
+                let distance = Vec2::new(i as Real, j as Real).distance(center) / cell_radius;
+                let weight = falloff.weight(distance);
+                if weight == 0.0 {
+                    continue;
+                }
+
+                let (x, y) = self.boundary.resolve(i, j, (self.width, self.height));
+                if self.obstacles[[x, y]] {
+                    continue;
+                }
+                f(&mut self.cells[[x, y]], weight);
+            }
+        }
+    }
+
+    fn apply_emitters(&mut self, delta: Real) {
+        for index in 0..self.emitters.len() {
+            let emitter = self.emitters[index];
+            let dye = Vec3::splat(emitter.density_rate * delta);
+            let temperature = emitter.temperature_rate * delta;
+            self.for_each_in_radius(
+                emitter.position,
+                emitter.radius,
+                Falloff::Gaussian,
+                |cell, weight| {
+                    cell.dye += weight * dye;
+                    cell.temperature += weight * temperature;
+                    cell.velocity = cell.velocity.lerp(emitter.velocity, weight);
+                },
+            );
+        }
+    }
+
+    /// Holds the two outermost rows along `edge` at `velocity` and `density`
+    /// every substep, replacing any inflow already on that edge. Pair it with
+    /// `BoundaryMode::Open` so the fluid can leave through the far edge.
+    pub fn set_inflow(&mut self, edge: Edge, velocity: Vec2, density: Real) {
+        self.remove_inflow(edge);
+        self.inflows.push(Inflow {
+            edge,
+            velocity,
+            density,
+        });
+    }
+
+    pub fn remove_inflow(&mut self, edge: Edge) {
+        self.inflows.retain(|inflow| inflow.edge != edge);
+    }
+
+    pub fn set_obstacle(&mut self, x: isize, y: isize, solid: bool) {
+        let (x, y) = self.boundary.resolve(x, y, (self.width, self.height));
+        self.obstacles[[x, y]] = solid;
+        if solid {
+            self.cells[[x, y]] = Cell::default();
+        }
+    }
+
+    pub fn step(&mut self, delta: Duration) {
+        self.step_with(delta, &mut CpuPressure);
+    }
+
+    /// Like `step`, but solves for pressure with `backend`.
+    pub fn step_with(&mut self, delta: Duration, backend: &mut dyn PressureBackend) {
+        self.step_timed(seconds(delta), None, backend, &mut StepTimings::default());
+    }
+
+    /// Like `step`, for callers that track time as seconds in a `Real`.
+    pub fn step_dt(&mut self, dt: Real) {
+        self.step_timed(dt, None, &mut CpuPressure, &mut StepTimings::default());
+    }
+
+    /// Like `step`, but always splits `delta` into exactly `substeps` solver
+    /// substeps, ignoring `adaptive_substeps`.
+    ///
+    /// `delta` isn't clamped to `max_dt`, so a fixed `substeps` always covers
+    /// the whole frame, as deterministic video needs. Keeping each substep
+    /// short enough to be stable is up to the caller.
+    pub fn step_substeps(&mut self, delta: Duration, substeps: usize) {
+        self.step_timed(
+            seconds(delta),
+            Some(substeps),
+            &mut CpuPressure,
+            &mut StepTimings::default(),
+        );
+    }
+
+    /// Like `step`, but reports how long each stage took.
+    pub fn step_profiled(&mut self, delta: Duration) -> StepTimings {
+        let start = Instant::now();
+        let mut timings = StepTimings::default();
+        self.step_timed(seconds(delta), None, &mut CpuPressure, &mut timings);
+        timings.total = start.elapsed();
+        timings
+    }
+
+    /// Like `step`, but calls `pre` before and `post` after every solver
+    /// substep with the substep's length in seconds, for coupling the fluid
+    /// to particles or rigid bodies at the solver's rate. Forces added with
+    /// `add_force` last until the end of the step, so `pre` should change the
+    /// cells' velocity directly.
+    pub fn step_coupled(
+        &mut self,
+        delta: Duration,
+        mut pre: impl FnMut(&mut Fluid, Real),
+        mut post: impl FnMut(&Fluid, Real),
+    ) {
+        self.step_hooked(
+            seconds(delta),
+            None,
+            &mut CpuPressure,
+            &mut StepTimings::default(),
+            &mut pre,
+            &mut post,
+        );
+    }
+
+    fn step_timed(
+        &mut self,
+        delta: Real,
+        substeps: Option<usize>,
+        backend: &mut dyn PressureBackend,
+        timings: &mut StepTimings,
+    ) {
+        self.step_hooked(
+            delta,
+            substeps,
+            backend,
+            timings,
+            &mut |_, _| {},
+            &mut |_, _| {},
+        );
+    }
+
+    fn step_hooked(
+        &mut self,
+        delta: Real,
+        substeps: Option<usize>,
+        backend: &mut dyn PressureBackend,
+        timings: &mut StepTimings,
+        pre: &mut dyn FnMut(&mut Fluid, Real),
+        post: &mut dyn FnMut(&Fluid, Real),
+    ) {
+        // Explicit substeps already keep each solver step short.
+        let delta = match substeps {
+            Some(_) => delta.max(0.0),
+            None => delta.clamp(0.0, seconds(self.max_dt)),
+        };
+        self.apply_emitters(delta);
+        self.apply_ambient_flow(delta);
+        let substeps = match substeps {
+            Some(substeps) => substeps.max(1),
+            None if self.adaptive_substeps => self.cfl_substeps(delta),
+            None => 1,
+        };
+
+        let substep_delta = delta / substeps as Real;
+        for _ in 0..substeps {
+            pre(self, substep_delta);
+            self.substep(substep_delta, backend, timings);
+            post(self, substep_delta);
+        }
+        self.forces.fill(Vec2::ZERO);
+        self.guard_nans();
+    }
+
+    fn guard_nans(&mut self) {
+        match self.nan_guard {
+            NanGuard::Off => {}
+            NanGuard::Panic => {
+                if let Some(((x, y), cell)) = self
+                    .cells
+                    .indexed_iter()
+                    .find(|(_, cell)| !cell.is_finite())
+                {
+                    panic!("non-finite cell at ({x}, {y}) after step: {cell:?}");
+                }
+            }
+            NanGuard::Reset => {
+                for cell in self.cells.current_mut() {
+                    if !cell.is_finite() {
+                        *cell = Cell::default();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Advances the simulation by `real_dt` in steps of `fixed_dt`, carrying
+    /// the remainder over to the next call. Returns the number of steps taken.
+    ///
+    /// `real_dt` is clamped to `max_dt` so a long stall doesn't queue up an
+    /// unbounded number of catch-up steps.
+    pub fn advance(&mut self, real_dt: Duration) -> usize {
+        self.advance_with(real_dt, &mut CpuPressure)
+    }
+
+    pub fn advance_with(&mut self, real_dt: Duration, backend: &mut dyn PressureBackend) -> usize {
+        if self.fixed_dt.is_zero() {
+            return 0;
+        }
+
+        self.accumulator += real_dt.min(self.max_dt);
+        let mut steps = 0;
+        while self.accumulator >= self.fixed_dt {
+            self.step_with(self.fixed_dt, backend);
+            self.accumulator -= self.fixed_dt;
+            steps += 1;
+        }
+        steps
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn save_to<W: Write>(&self, writer: W) -> eyre::Result<()> {
+        bincode::serialize_into(writer, self)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn load_from<R: Read>(reader: R) -> eyre::Result<Self> {
+        Ok(bincode::deserialize_from(reader)?)
+    }
+
+    /// Renders density the way `FluidTexture` does in density mode: dye
+    /// colors for `Colormap::Dye`, otherwise density through `colormap`.
+    /// Row 0 is the top of the grid (highest `y`).
+    #[cfg(feature = "image")]
+    pub fn to_image(&self, colormap: Colormap) -> image::RgbaImage {
+        image::RgbaImage::from_fn(self.width as u32, self.height as u32, |x, row| {
+            let (x, y) = (x as usize, self.height - 1 - row as usize);
+            if self.obstacles[[x, y]] {
+                return image::Rgba(OBSTACLE_COLOR);
+            }
+
+            let cell = &self.cells[[x, y]];
+            let color = match colormap {
+                Colormap::Dye => {
+                    let dye = cell.dye.clamp(Vec3::ZERO, Vec3::ONE);
+                    glam::Vec3::new(to_f32(dye.x), to_f32(dye.y), to_f32(dye.z))
+                }
+                _ => colormap.sample(to_f32(cell.density())),
+            } * u8::MAX as f32;
+            image::Rgba([color.x as u8, color.y as u8, color.z as u8, u8::MAX])
+        })
+    }
+
+    /// Builds a fluid with one cell per pixel of the image at `path`, laid
+    /// out like `to_image`. Black pixels become obstacles and every other
+    /// pixel starts with its luminance as gray dye.
+    #[cfg(feature = "image")]
+    pub fn from_image(
+        path: impl AsRef<Path>,
+        diffusion: Real,
+        viscosity: Real,
+    ) -> eyre::Result<Self> {
+        let image = image::open(path)?.into_luma8();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let mut fluid = Fluid::with_dimensions(diffusion, viscosity, width, height);
+        for (x, row, &image::Luma([luma])) in image.enumerate_pixels() {
+            let (x, y) = (x as usize, height - 1 - row as usize);
+            if luma == 0 {
+                fluid.obstacles[[x, y]] = true;
+            } else {
+                fluid.cells[[x, y]].dye = Vec3::splat(luma as Real / u8::MAX as Real);
+            }
+        }
+        let cells = fluid.cells.current().clone();
+        *fluid.cells.previous_mut() = cells;
+        Ok(fluid)
+    }
+
+    #[cfg(feature = "image")]
+    pub fn save_png(&self, path: impl AsRef<Path>, colormap: Colormap) -> eyre::Result<()> {
+        self.to_image(colormap)
+            .save_with_format(path, image::ImageFormat::Png)?;
+        Ok(())
+    }
+
+    /// Writes one `x,y,density,vx,vy` row per cell after a header, with `x`
+    /// varying fastest.
+    pub fn export_csv<W: Write>(&self, mut writer: W) -> eyre::Result<()> {
+        writeln!(writer, "x,y,density,vx,vy")?;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let cell = &self.cells[[x, y]];
+                writeln!(
+                    writer,
+                    "{x},{y},{},{},{}",
+                    cell.density(),
+                    cell.velocity.x,
+                    cell.velocity.y
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a legacy ASCII VTK file with one point per cell, unit spacing,
+    /// density as a scalar field and velocity as a vector field.
+    pub fn export_vtk<W: Write>(&self, mut writer: W) -> eyre::Result<()> {
+        let points = self.width * self.height;
+        writeln!(writer, "# vtk DataFile Version 3.0")?;
+        writeln!(writer, "fluidsim")?;
+        writeln!(writer, "ASCII")?;
+        writeln!(writer, "DATASET STRUCTURED_POINTS")?;
+        writeln!(writer, "DIMENSIONS {} {} 1", self.width, self.height)?;
+        writeln!(writer, "ORIGIN 0 0 0")?;
+        writeln!(writer, "SPACING 1 1 1")?;
+        writeln!(writer, "POINT_DATA {points}")?;
+        writeln!(writer, "SCALARS density float 1")?;
+        writeln!(writer, "LOOKUP_TABLE default")?;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                writeln!(writer, "{}", self.cells[[x, y]].density())?;
+            }
+        }
+        writeln!(writer, "VECTORS velocity float")?;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let velocity = self.cells[[x, y]].velocity;
+                writeln!(writer, "{} {} 0", velocity.x, velocity.y)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.fill(Cell::default());
+        self.cells.previous_mut().fill(Cell::default());
+        self.divergence.fill(0.0);
+        self.pressure.fill(0.0);
+        self.previous_pressure.fill(0.0);
+        self.forces.fill(Vec2::ZERO);
+        self.accumulator = Duration::ZERO;
+        if let Some(detail) = &mut self.detail {
+            detail.dye.fill(Vec3::ZERO);
+            detail.dye.previous_mut().fill(Vec3::ZERO);
+        }
+    }
+
+    pub fn resize(&mut self, new_size: usize) {
+        self.resize_dimensions(new_size, new_size);
+    }
+
+    /// Resamples the dye and velocity fields onto a `width` by `height` grid.
+    /// Growing interpolates bilinearly, shrinking averages the old cells each
+    /// new cell covers. Pressure and pending forces are reset, and `detail`
+    /// keeps its factor but is refilled from the resized cells.
+    pub fn resize_dimensions(&mut self, width: usize, height: usize) {
+        let ratio = Vec2::new(
+            self.width as Real / width as Real,
+            self.height as Real / height as Real,
+        );
+        let samples_x = ratio.x.ceil().max(1.0) as usize;
+        let samples_y = ratio.y.ceil().max(1.0) as usize;
+        let weight = 1.0 / (samples_x * samples_y) as Real;
+        // Reflecting would flip the velocity of samples that land just past
+        // the edge, so only keep wrapping and clamp otherwise.
+        let keep_wrap = |mode| match mode {
+            BoundaryMode::Wrap => BoundaryMode::Wrap,
+            BoundaryMode::Reflect | BoundaryMode::Open => BoundaryMode::Open,
+        };
+        let boundary = Boundary::new(keep_wrap(self.boundary.x), keep_wrap(self.boundary.y));
+
+        let old = &self.cells;
+        let cells = Array2::from_shape_fn((width, height), |(x, y)| {
+            let mut cell = Cell::default();
+            for sample_x in 0..samples_x {
+                for sample_y in 0..samples_y {
+                    let offset = Vec2::new(
+                        (sample_x as Real + 0.5) / samples_x as Real,
+                        (sample_y as Real + 0.5) / samples_y as Real,
+                    );
+                    let position = (Vec2::new(x as Real, y as Real) + offset) * ratio - 0.5;
+                    cell += sample_cell(old, boundary, position) * weight;
+                }
+            }
+            cell
+        });
+
+        let old_obstacles = &self.obstacles;
+        let obstacles = Array2::from_shape_fn((width, height), |(x, y)| {
+            let old_x = clamp_index(((x as Real + 0.5) * ratio.x) as isize, self.width);
+            let old_y = clamp_index(((y as Real + 0.5) * ratio.y) as isize, self.height);
+            old_obstacles[[old_x, old_y]]
+        });
+
+        self.width = width;
+        self.height = height;
+        self.cells.set(cells);
+        self.obstacles = obstacles;
+        self.divergence = Array2::zeros((width, height));
+        self.pressure = Array2::zeros((width, height));
+        self.previous_pressure = Array2::zeros((width, height));
+        self.forces = Array2::default((width, height));
+
+        if let Some(detail) = &mut self.detail {
+            let factor = detail.factor;
+            let cells = &self.cells;
+            detail.dye.set(Array2::from_shape_fn(
+                (width * factor, height * factor),
+                |(x, y)| sample_cell(cells, boundary, detail_to_cell(factor, x, y)).dye,
+            ));
+        }
+    }
+
+    pub fn step_n(&mut self, delta: Duration, steps: usize) {
+        for _ in 0..steps {
+            self.step(delta);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ((usize, usize), &Cell)> {
+        self.cells.indexed_iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = ((usize, usize), &mut Cell)> {
+        self.cells.indexed_iter_mut()
+    }
+
+    pub fn density_at(&self, x: isize, y: isize) -> Real {
+        self[(x, y)].density()
+    }
+
+    /// Density at `position` in normalized coordinates, interpolated
+    /// bilinearly between the surrounding cell centers.
+    pub fn sample_density(&self, position: Vec2) -> Real {
+        self.sample(position).density()
+    }
+
+    /// Velocity at `position` in normalized coordinates, interpolated like
+    /// `sample_density`.
+    pub fn sample_velocity(&self, position: Vec2) -> Vec2 {
+        self.sample(position).velocity
+    }
+
+    /// Largest absolute velocity divergence over all fluid cells, in inverse
+    /// seconds, from central differences. Projection zeroes the divergence
+    /// of the compact stencil its pressure solve uses, which this wider
+    /// stencil doesn't see, so even a converged solve leaves it above zero:
+    /// compare it against the value before projecting rather than against
+    /// zero.
+    pub fn max_divergence(&self) -> Real {
+        self.divergence_field()
+            .iter()
+            .map(|divergence| divergence.abs())
+            .fold(0.0, Real::max)
+    }
+
+    /// Per-cell velocity divergence in inverse seconds, zero inside
+    /// obstacles.
+    pub fn divergence_field(&self) -> Array2<Real> {
+        let scale = self.scale();
+        Array2::from_shape_fn((self.width, self.height), |(x, y)| {
+            if self.obstacles[[x, y]] {
+                0.0
+            } else {
+                scale * velocity_divergence(&self.cells, self.boundary, x as isize, y as isize)
+            }
+        })
+    }
+
+    /// Copies of single fields of the cells, laid out like `cells`.
+    pub fn densities(&self) -> Array2<Real> {
+        self.cells.map(Cell::density)
+    }
+
+    pub fn velocities(&self) -> Array2<Vec2> {
+        self.cells.map(|cell| cell.velocity)
+    }
+
+    pub fn temperatures(&self) -> Array2<Real> {
+        self.cells.map(|cell| cell.temperature)
+    }
+
+    pub fn total_density(&self) -> Real {
+        self.cells.iter().map(Cell::density).sum()
+    }
+
+    /// Total of `0.5 * mass * |velocity|²` over the cells. Each cell has
+    /// mass 1, or `1 + density` with `variable_density`. In a closed box
+    /// without forces this should only fall; growth means the solver is
+    /// adding energy.
+    pub fn total_kinetic_energy(&self) -> Real {
+        self.cells
+            .iter()
+            .map(|cell| 0.5 * self.cell_mass(cell) * cell.velocity.length_squared())
+            .sum()
+    }
+
+    /// Total of `mass * velocity` over the cells, with masses as in
+    /// `total_kinetic_energy`.
+    pub fn total_momentum(&self) -> Vec2 {
+        self.cells
+            .iter()
+            .map(|cell| self.cell_mass(cell) * cell.velocity)
+            .sum()
+    }
+
+    fn cell_mass(&self, cell: &Cell) -> Real {
+        if self.variable_density {
+            mass(cell)
+        } else {
+            1.0
+        }
+    }
+
+    fn cfl_substeps(&self, delta: Real) -> usize {
+        let max_speed = self
+            .cells
+            .iter()
+            .map(|cell| cell.velocity.length())
+            .fold(0.0, Real::max);
+        let cells_per_step = max_speed * delta * self.scale();
+        (cells_per_step.ceil() as usize).clamp(1, MAX_SUBSTEPS)
+    }
+
+    fn substep(
+        &mut self,
+        delta: Real,
+        backend: &mut dyn PressureBackend,
+        timings: &mut StepTimings,
+    ) {
+        self.apply_inflows();
+        self.apply_velocity_regions(delta);
+        self.apply_forces(delta);
+        let start = Instant::now();
+        self.diffuse(delta);
+        timings.diffuse += start.elapsed();
+        let start = Instant::now();
+        self.project(backend);
+        timings.project += start.elapsed();
+        if self.vorticity != 0.0 {
+            self.confine_vorticity(delta);
+        }
+        let start = Instant::now();
+        self.advect(delta);
+        timings.advect += start.elapsed();
+        let start = Instant::now();
+        self.project(backend);
+        timings.project += start.elapsed();
+        if self.density_decay != 0.0 || self.velocity_damping != 0.0 {
+            self.dissipate(delta);
+        }
+    }
+
+    fn dissipate(&mut self, delta: Real) {
+        let dye_factor = (1.0 - self.density_decay * delta).max(0.0);
+        let velocity_factor = (1.0 - self.velocity_damping * delta).max(0.0);
+        for cell in self.cells.current_mut() {
+            cell.dye *= dye_factor;
+            cell.temperature *= dye_factor;
+            cell.velocity *= velocity_factor;
+        }
+        if let Some(detail) = &mut self.detail {
+            detail.dye.map_inplace(|dye| *dye *= dye_factor);
+        }
+    }
+
+    fn apply_velocity_regions(&mut self, delta: Real) {
+        let clip = |min: isize, max: isize, size: usize| {
+            min.max(0) as usize..(max + 1).clamp(0, size as isize) as usize
+        };
+        for region in &self.velocity_regions {
+            for x in clip(region.min.0, region.max.0, self.width) {
+                for y in clip(region.min.1, region.max.1, self.height) {
+                    if self.obstacles[[x, y]] {
+                        continue;
+                    }
+                    let cell = &mut self.cells[[x, y]];
+                    match region.mode {
+                        RegionMode::Set => cell.velocity = region.velocity,
+                        RegionMode::Add => cell.velocity += region.velocity * delta,
+                    }
+                }
+            }
+        }
+    }
+
+    fn apply_inflows(&mut self) {
+        let (width, height) = (self.width, self.height);
+        for inflow in &self.inflows {
+            let dye = Vec3::splat(inflow.density);
+            let mut apply = |x: usize, y: usize| {
+                if !self.obstacles[[x, y]] {
+                    let cell = &mut self.cells[[x, y]];
+                    cell.dye = dye;
+                    cell.velocity = inflow.velocity;
+                }
+            };
+            for depth in 0..2.min(width.min(height)) {
+                match inflow.edge {
+                    Edge::Left => (0..height).for_each(|y| apply(depth, y)),
+                    Edge::Right => (0..height).for_each(|y| apply(width - 1 - depth, y)),
+                    Edge::Bottom => (0..width).for_each(|x| apply(x, depth)),
+                    Edge::Top => (0..width).for_each(|x| apply(x, height - 1 - depth)),
+                }
+            }
+        }
+    }
+
+    fn apply_forces(&mut self, delta: Real) {
+        let gravity = self.gravity;
+        let (alpha, beta) = (self.buoyancy_alpha, self.buoyancy_beta);
+        let variable_density = self.variable_density;
+        Zip::from(self.cells.current_mut())
+            .and(&self.forces)
+            .for_each(|cell, &force| {
+                let buoyancy = (beta * cell.temperature - alpha * cell.density()) * Vec2::Y;
+                let mass = if variable_density { mass(cell) } else { 1.0 };
+                cell.velocity += delta * (force + cell.density() * gravity + buoyancy) / mass;
+            });
+    }
+
+    fn sample(&self, position: Vec2) -> Cell {
+        let size = Vec2::new(self.width as Real, self.height as Real);
+        sample_cell(&self.cells, self.boundary, position * size - 0.5)
+    }
+
+    fn scale(&self) -> Real {
+        self.width.max(self.height) as Real
+    }
+
+    fn diffuse(&mut self, delta: Real) {
+        let scale = self.scale();
+        let diffusion = if self.diffuse_density {
+            self.diffusion
+        } else {
+            0.0
+        };
+        let a_density = delta * diffusion * scale * scale;
+        let a_velocity = delta * self.viscosity * scale * scale;
+        if a_density == 0.0 && a_velocity == 0.0 {
+            return;
+        }
+
+        self.cells.swap();
+
+        let mut scratch = self.cells.current().clone();
+        for _ in 0..self.solver_iterations {
+            let boundary = self.boundary;
+            let (cells, prev_cells) = self.cells.split_mut();
+            let obstacles = &self.obstacles;
+            relax(cells, &mut scratch, |cells, x, y| {
+                let i = x as isize;
+                let j = y as isize;
+
+                let neighbor = |i, j| {
+                    if get_scalar(obstacles, boundary, i, j) {
+                        Cell {
+                            velocity: Vec2::ZERO,
+                            ..cells[[x, y]]
+                        }
+                    } else {
+                        get_cell(cells, boundary, i, j)
+                    }
+                };
+                let left = neighbor(i - 1, j);
+                let right = neighbor(i + 1, j);
+                let top = neighbor(i, j - 1);
+                let bottom = neighbor(i, j + 1);
+
+                let mut cell = prev_cells[[x, y]];
+                if a_density != 0.0 {
+                    cell.dye = (cell.dye
+                        + a_density * (left.dye + right.dye + top.dye + bottom.dye))
+                        / (1.0 + 4.0 * a_density);
+                    cell.temperature = (cell.temperature
+                        + a_density
+                            * (left.temperature
+                                + right.temperature
+                                + top.temperature
+                                + bottom.temperature))
+                        / (1.0 + 4.0 * a_density);
+                }
+                if a_velocity != 0.0 {
+                    cell.velocity = (cell.velocity
+                        + a_velocity
+                            * (left.velocity + right.velocity + top.velocity + bottom.velocity))
+                        / (1.0 + 4.0 * a_velocity);
+                }
+                cell
+            });
+            self.set_cell_bnd();
+        }
+    }
+
+    fn project(&mut self, backend: &mut dyn PressureBackend) {
+        if backend.project(self) {
+            self.set_bnd(BoundaryField::VelocityX);
+            self.set_bnd(BoundaryField::VelocityY);
+            return;
+        }
+
+        let h = 1.0 / self.scale();
+        let boundary = self.boundary;
+        let cells = &self.cells;
+        for_each_indexed(&mut self.divergence, |(x, y), divergence| {
+            let i = x as isize;
+            let j = y as isize;
+
+            *divergence = -h * velocity_divergence(cells, boundary, i, j);
+        });
+        if self.warm_start {
+            mem::swap(&mut self.pressure, &mut self.previous_pressure);
+        } else {
+            self.pressure.fill(0.0);
+        }
+        self.set_bnd(BoundaryField::Divergence);
+        self.set_bnd(BoundaryField::Pressure);
+
+        backend.solve(self);
+        self.set_bnd(BoundaryField::Pressure);
+
+        let pressure = &self.pressure;
+        let obstacles = &self.obstacles;
+        for_each_indexed(&mut self.cells, |(x, y), cell| {
+            let i = x as isize;
+            let j = y as isize;
+
+            cell.velocity -=
+                0.5 * Vec2::new(
+                    get_pressure(pressure, obstacles, boundary, (x, y), i + 1, j)
+                        - get_pressure(pressure, obstacles, boundary, (x, y), i - 1, j),
+                    get_pressure(pressure, obstacles, boundary, (x, y), i, j + 1)
+                        - get_pressure(pressure, obstacles, boundary, (x, y), i, j - 1),
+                ) / h;
+        });
+        self.set_bnd(BoundaryField::VelocityX);
+        self.set_bnd(BoundaryField::VelocityY);
+    }
+
+    fn relax_pressure(&mut self, iterations: usize) {
+        let boundary = self.boundary;
+        let mut scratch = self.pressure.clone();
+        for _ in 0..iterations {
+            let divergence = &self.divergence;
+            let obstacles = &self.obstacles;
+            relax(&mut self.pressure, &mut scratch, |pressure, x, y| {
+                let i = x as isize;
+                let j = y as isize;
+
+                0.25 * (divergence[[x, y]]
+                    + get_pressure(pressure, obstacles, boundary, (x, y), i - 1, j)
+                    + get_pressure(pressure, obstacles, boundary, (x, y), i + 1, j)
+                    + get_pressure(pressure, obstacles, boundary, (x, y), i, j - 1)
+                    + get_pressure(pressure, obstacles, boundary, (x, y), i, j + 1))
+            });
+            self.set_bnd(BoundaryField::Pressure);
+        }
+    }
 
-impl Fluid {
-    pub fn new(diffusion: f32, viscosity: f32, size: usize) -> Self {
-        Self {
-            diffusion,
-            viscosity,
-            size,
-            cells: Array2::default((size, size)),
-            prev_cells: Array2::default((size, size)),
+    /// The divergence with its mean over fluid cells removed, or `None` if
+    /// there are no fluid cells.
+    ///
+    /// Every boundary mode leaves pressure defined only up to a constant, so
+    /// the divergence has to sum to zero for a solution to exist. Removing
+    /// the mean keeps the iterative solvers from drifting along the null
+    /// space.
+    fn pressure_rhs(&self) -> Option<Array2<Real>> {
+        let (sum, fluid_cells) = Zip::from(&self.divergence).and(&self.obstacles).fold(
+            (0.0, 0),
+            |(sum, count), &divergence, &solid| {
+                if solid {
+                    (sum, count)
+                } else {
+                    (sum + to_f64(divergence), count + 1)
+                }
+            },
+        );
+        if fluid_cells == 0 {
+            return None;
         }
+        let mean = (sum / fluid_cells as f64) as Real;
+        // Solid cells are pinned to their current value so the system stays
+        // symmetric.
+        Some(
+            Zip::from(&self.divergence)
+                .and(&self.obstacles)
+                .map_collect(|&divergence, &solid| if solid { 0.0 } else { divergence - mean }),
+        )
     }
 
-    pub fn step(&mut self, delta: Duration) {
-        let delta = delta.as_secs_f32();
-        self.diffuse(delta);
-        self.project();
-        self.advect(delta);
-        self.project();
+    fn conjugate_gradient(&mut self, tolerance: Real, max_iterations: usize) {
+        let boundary = self.boundary;
+        let obstacles = &self.obstacles;
+        let Some(rhs) = self.pressure_rhs() else {
+            return;
+        };
+
+        let mut residual = Array2::zeros(self.pressure.raw_dim());
+        apply_pressure_laplacian(&self.pressure, obstacles, boundary, &mut residual);
+        Zip::from(&mut residual)
+            .and(&rhs)
+            .and(obstacles)
+            .for_each(|residual, &rhs, &solid| {
+                *residual = if solid { 0.0 } else { rhs - *residual };
+            });
+        let threshold = to_f64(tolerance) * dot(&rhs, &rhs).sqrt();
+
+        let mut direction = residual.clone();
+        let mut product = Array2::zeros(self.pressure.raw_dim());
+        let mut residual_dot = dot(&residual, &residual);
+        for _ in 0..max_iterations {
+            if residual_dot.sqrt() <= threshold {
+                break;
+            }
+
+            apply_pressure_laplacian(&direction, obstacles, boundary, &mut product);
+            let alpha = residual_dot / dot(&direction, &product);
+            if !alpha.is_finite() {
+                break;
+            }
+            let alpha = alpha as Real;
+            Zip::from(&mut self.pressure)
+                .and(&direction)
+                .for_each(|pressure, &direction| *pressure += alpha * direction);
+            Zip::from(&mut residual)
+                .and(&product)
+                .for_each(|residual, &product| *residual -= alpha * product);
+
+            let next_residual_dot = dot(&residual, &residual);
+            let beta = (next_residual_dot / residual_dot) as Real;
+            residual_dot = next_residual_dot;
+            Zip::from(&mut direction)
+                .and(&residual)
+                .for_each(|direction, &residual| *direction = residual + beta * *direction);
+        }
     }
 
-    fn diffuse(&mut self, delta: f32) {
-        mem::swap(&mut self.cells, &mut self.prev_cells);
+    fn multigrid(&mut self, levels: usize, cycles: usize) {
+        let Some(rhs) = self.pressure_rhs() else {
+            return;
+        };
+        for _ in 0..cycles {
+            v_cycle(
+                &mut self.pressure,
+                &rhs,
+                &self.obstacles,
+                self.boundary,
+                levels,
+            );
+        }
+    }
+
+    /// Per-cell vorticity `dv_y/dx - dv_x/dy` in inverse seconds, positive
+    /// for counterclockwise rotation.
+    pub fn curl_field(&self) -> Array2<Real> {
+        let half_scale = 0.5 * self.scale();
+        Array2::from_shape_fn((self.width, self.height), |(x, y)| {
+            let i = x as isize;
+            let j = y as isize;
+            half_scale
+                * (get_cell(&self.cells, self.boundary, i + 1, j).velocity.y
+                    - get_cell(&self.cells, self.boundary, i - 1, j).velocity.y
+                    - get_cell(&self.cells, self.boundary, i, j + 1).velocity.x
+                    + get_cell(&self.cells, self.boundary, i, j - 1).velocity.x)
+        })
+    }
 
-        let a_density = delta * self.diffusion * (self.size * self.size) as f32;
-        let a_velocity = delta * self.viscosity * (self.size * self.size) as f32;
+    fn confine_vorticity(&mut self, delta: Real) {
+        let curl = self.curl_field();
+        let h = 1.0 / self.scale();
 
-        for _ in 0..20 {
-            for x in 0..self.size {
-                let i = x as isize;
-                for y in 0..self.size {
-                    let j = y as isize;
+        for ((x, y), cell) in self.cells.indexed_iter_mut() {
+            let i = x as isize;
+            let j = y as isize;
 
-                    self.cells[[x, y]].density = (self.prev_cells[[x, y]].density
-                        + a_density
-                            * (get_cell(&self.cells, i - 1, j).density
-                                + get_cell(&self.cells, i + 1, j).density
-                                + get_cell(&self.cells, i, j - 1).density
-                                + get_cell(&self.cells, i, j + 1).density))
-                        / (1.0 + 4.0 * a_density);
+            let gradient = 0.5
+                * Vec2::new(
+                    get_scalar(&curl, self.boundary, i + 1, j).abs()
+                        - get_scalar(&curl, self.boundary, i - 1, j).abs(),
+                    get_scalar(&curl, self.boundary, i, j + 1).abs()
+                        - get_scalar(&curl, self.boundary, i, j - 1).abs(),
+                );
+            let normal = gradient.normalize_or_zero();
 
-                    self.cells[[x, y]].velocity = (self.prev_cells[[x, y]].velocity
-                        + a_density
-                            * (get_cell(&self.cells, i - 1, j).velocity
-                                + get_cell(&self.cells, i + 1, j).velocity
-                                + get_cell(&self.cells, i, j - 1).velocity
-                                + get_cell(&self.cells, i, j + 1).velocity))
-                        / (1.0 + 4.0 * a_velocity);
+            let omega = curl[[x, y]];
+            let force = self.vorticity * h * Vec2::new(normal.y * omega, -normal.x * omega);
+            cell.velocity += delta * force;
+        }
+        self.set_bnd(BoundaryField::VelocityX);
+        self.set_bnd(BoundaryField::VelocityY);
+    }
+
+    fn advect(&mut self, delta: Real) {
+        self.push_detail();
+        let total_dye = self.conserve_density.then(|| self.total_dye());
+        self.cells.swap();
+
+        // Backtrace along the velocity either way, but with variable density
+        // transport momentum and divide the advected mass back out after.
+        let momentum = self.variable_density.then(|| {
+            let mut momentum = self.cells.previous().clone();
+            momentum.map_inplace(|cell| cell.velocity *= mass(cell));
+            momentum
+        });
+
+        let delta_size = delta * self.scale();
+        let trace = Trace {
+            boundary: self.boundary,
+            order: self.backtrace,
+        };
+        let (cells, prev_cells) = self.cells.split_mut();
+        let source = momentum.as_ref().unwrap_or(prev_cells);
+        advect_cells(cells, source, prev_cells, trace, delta_size);
+
+        match self.advection {
+            AdvectionScheme::SemiLagrangian => {}
+            AdvectionScheme::MacCormack => {
+                let mut backward = cells.clone();
+                advect_cells(&mut backward, cells, prev_cells, trace, -delta_size);
+
+                for_each_indexed(cells, |(x, y), cell| {
+                    let error = source[[x, y]] - backward[[x, y]];
+                    *cell = clamp_to_source(
+                        *cell + error * 0.5,
+                        source,
+                        prev_cells,
+                        trace,
+                        (x, y),
+                        delta_size,
+                    );
+                });
+            }
+            AdvectionScheme::Bfecc { clamp } => {
+                let mut corrected = cells.clone();
+                advect_cells(&mut corrected, cells, prev_cells, trace, -delta_size);
+                Zip::from(&mut corrected)
+                    .and(source)
+                    .for_each(|backward, &source| {
+                        *backward = source + (source - *backward) * 0.5;
+                    });
+                advect_cells(cells, &corrected, prev_cells, trace, delta_size);
+
+                if clamp {
+                    for_each_indexed(cells, |(x, y), cell| {
+                        *cell =
+                            clamp_to_source(*cell, source, prev_cells, trace, (x, y), delta_size);
+                    });
                 }
             }
         }
-    }
 
-    fn project(&mut self) {
-        let h = 1.0 / self.size as f32;
-        for x in 0..self.size {
-            let i = x as isize;
-            for y in 0..self.size {
-                let j = y as isize;
+        if self.variable_density {
+            self.cells.map_inplace(|cell| cell.velocity /= mass(cell));
+        }
+        self.set_cell_bnd();
 
-                self.prev_cells[[x, y]].velocity.y = -0.5
-                    * h
-                    * (get_cell(&self.cells, i + 1, j).velocity.x
-                        - get_cell(&self.cells, i - 1, j).velocity.x
-                        + get_cell(&self.cells, i, j + 1).velocity.y
-                        - get_cell(&self.cells, i, j - 1).velocity.y);
+        if let Some(detail) = &mut self.detail {
+            detail.dye.swap();
+            let factor = detail.factor;
+            let (dye, source) = detail.dye.split_mut();
+            let velocities = self.cells.previous();
+            for_each_indexed(dye, |(x, y), dye| {
+                let start = detail_to_cell(factor, x, y);
+                let velocity = sample_cell(velocities, trace.boundary, start).velocity;
+                let position = backtrace_from(velocities, trace, start, velocity, delta_size);
+                let source_pos = (position + 0.5) * factor as Real - 0.5;
+                *dye = sample_dye(source, trace.boundary, source_pos);
+            });
+            self.clear_detail_bnd();
+            self.pull_detail();
+        }
 
-                self.prev_cells[[x, y]].velocity.x = 0.0;
+        if let Some(before) = total_dye {
+            let after = self.total_dye();
+            let scale = Vec3::select(
+                after.cmpgt(Vec3::splat(Real::EPSILON)),
+                before / after,
+                Vec3::ONE,
+            );
+            self.cells.map_inplace(|cell| cell.dye *= scale);
+            if let Some(detail) = &mut self.detail {
+                detail.dye.map_inplace(|dye| *dye *= scale);
             }
         }
+    }
 
-        for _ in 0..20 {
-            for x in 0..self.size {
-                let i = x as isize;
-                for y in 0..self.size {
-                    let j = y as isize;
+    /// Carries changes made to the cells' dye since the last advection, by
+    /// sources, diffusion or the caller, over to `detail`. Each block is
+    /// scaled by how much its cell's dye changed, keeping its detail, or
+    /// filled evenly where it was empty.
+    fn push_detail(&mut self) {
+        let Some(detail) = &mut self.detail else {
+            return;
+        };
+        let means = detail.block_means((self.width, self.height));
+        let factor = detail.factor;
+        let cells = &self.cells;
+        for_each_indexed(detail.dye.current_mut(), |(x, y), dye| {
+            let (x, y) = (x / factor, y / factor);
+            let (target, mean) = (cells[[x, y]].dye, means[[x, y]]);
+            *dye = Vec3::select(
+                mean.cmpgt(Vec3::splat(Real::EPSILON)),
+                *dye * (target / mean),
+                target,
+            );
+        });
+    }
 
-                    self.prev_cells[[x, y]].velocity.x = 0.25
-                        * (self.prev_cells[[x, y]].velocity.y
-                            + get_cell(&self.prev_cells, i - 1, j).velocity.x
-                            + get_cell(&self.prev_cells, i + 1, j).velocity.x
-                            + get_cell(&self.prev_cells, i, j - 1).velocity.x
-                            + get_cell(&self.prev_cells, i, j + 1).velocity.x);
-                }
+    /// Sets each cell's dye to the average of its block of `detail`.
+    fn pull_detail(&mut self) {
+        let Some(detail) = &self.detail else {
+            return;
+        };
+        let means = detail.block_means((self.width, self.height));
+        Zip::from(self.cells.current_mut())
+            .and(&means)
+            .for_each(|cell, &mean| cell.dye = mean);
+    }
+
+    /// Applies the dye boundary conditions the cells get from `set_bnd` to
+    /// `detail`: nothing inside obstacles, and nothing in the outer ring of
+    /// cells on edges with `ScalarBoundary::Dirichlet`.
+    fn clear_detail_bnd(&mut self) {
+        let Some(detail) = &mut self.detail else {
+            return;
+        };
+        let factor = detail.factor;
+        let absorb = self.density_boundary == ScalarBoundary::Dirichlet;
+        let on_edge = |index: usize, size: usize, mode| {
+            absorb && mode != BoundaryMode::Wrap && (index == 0 || index + 1 == size)
+        };
+        let (width, height, boundary) = (self.width, self.height, self.boundary);
+        let obstacles = &self.obstacles;
+        for_each_indexed(detail.dye.current_mut(), |(x, y), dye| {
+            let (x, y) = (x / factor, y / factor);
+            if obstacles[[x, y]] || on_edge(x, width, boundary.x) || on_edge(y, height, boundary.y)
+            {
+                *dye = Vec3::ZERO;
             }
+        });
+    }
+
+    fn total_dye(&self) -> Vec3 {
+        self.cells.iter().map(|cell| cell.dye).sum()
+    }
+
+    fn set_cell_bnd(&mut self) {
+        self.set_bnd(BoundaryField::Dye);
+        self.set_bnd(BoundaryField::Temperature);
+        self.set_bnd(BoundaryField::VelocityX);
+        self.set_bnd(BoundaryField::VelocityY);
+    }
+
+    fn set_bnd(&mut self, field: BoundaryField) {
+        if !self.boundary.wraps() {
+            self.set_edge_bnd(field);
         }
+        self.set_obstacle_bnd(field);
+    }
 
-        for x in 0..self.size {
-            let i = x as isize;
-            for y in 0..self.size {
-                let j = y as isize;
+    fn set_obstacle_bnd(&mut self, field: BoundaryField) {
+        let clear: fn(&mut Cell) = match field {
+            BoundaryField::Dye => |cell| cell.dye = Vec3::ZERO,
+            BoundaryField::Temperature => |cell| cell.temperature = 0.0,
+            BoundaryField::VelocityX => |cell| cell.velocity.x = 0.0,
+            BoundaryField::VelocityY => |cell| cell.velocity.y = 0.0,
+            BoundaryField::Divergence | BoundaryField::Pressure => return,
+        };
+        Zip::from(self.cells.current_mut())
+            .and(&self.obstacles)
+            .for_each(|cell, &solid| {
+                if solid {
+                    clear(cell);
+                }
+            });
+    }
+
+    fn set_edge_bnd(&mut self, field: BoundaryField) {
+        // Zero signs clear the ring instead of copying the inner cells.
+        let absorb = self.density_boundary == ScalarBoundary::Dirichlet
+            && matches!(field, BoundaryField::Dye | BoundaryField::Temperature);
+        // `None` leaves a wrapping axis without edges.
+        let sign = |mode, normal| match mode {
+            BoundaryMode::Wrap => None,
+            _ if absorb => Some(0.0),
+            BoundaryMode::Reflect if field == normal => Some(-1.0),
+            BoundaryMode::Reflect | BoundaryMode::Open => Some(1.0),
+        };
+        let sign_x = sign(self.boundary.x, BoundaryField::VelocityX);
+        let sign_y = sign(self.boundary.y, BoundaryField::VelocityY);
 
-                self.cells[[x, y]].velocity -=
-                    0.5 * Vec2::new(
-                        get_cell(&self.prev_cells, i + 1, j).velocity.x
-                            - get_cell(&self.prev_cells, i - 1, j).velocity.x,
-                        get_cell(&self.prev_cells, i, j + 1).velocity.x
-                            - get_cell(&self.prev_cells, i, j - 1).velocity.x,
-                    ) / h;
+        match field {
+            BoundaryField::Dye => {
+                set_boundary_ring(&mut self.cells, sign_x, sign_y, |cell| &mut cell.dye)
+            }
+            BoundaryField::Temperature => {
+                set_boundary_ring(&mut self.cells, sign_x, sign_y, |cell| {
+                    &mut cell.temperature
+                })
+            }
+            BoundaryField::VelocityX => {
+                set_boundary_ring(&mut self.cells, sign_x, sign_y, |cell| &mut cell.velocity.x)
+            }
+            BoundaryField::VelocityY => {
+                set_boundary_ring(&mut self.cells, sign_x, sign_y, |cell| &mut cell.velocity.y)
+            }
+            BoundaryField::Divergence => {
+                set_boundary_ring(&mut self.divergence, sign_x, sign_y, |value| value)
+            }
+            BoundaryField::Pressure => {
+                set_boundary_ring(&mut self.pressure, sign_x, sign_y, |value| value)
             }
         }
     }
+}
+
+fn validate_coefficient(name: &'static str, value: Real) -> Result<Real, InvalidCoefficient> {
+    if value.is_finite() {
+        Ok(value.max(0.0))
+    } else {
+        Err(InvalidCoefficient { name, value })
+    }
+}
 
-    fn advect(&mut self, delta: f32) {
-        mem::swap(&mut self.cells, &mut self.prev_cells);
+fn mass(cell: &Cell) -> Real {
+    1.0 + cell.density()
+}
 
-        let delta_size = delta * self.size as f32;
+/// What `backtrace` needs to know besides the velocities.
+#[derive(Debug, Clone, Copy)]
+struct Trace {
+    boundary: Boundary,
+    order: BacktraceOrder,
+}
 
-        for ((x, y), cell) in self.cells.indexed_iter_mut() {
-            let source_pos =
-                Vec2::new(x as f32, y as f32) - delta_size * self.prev_cells[[x, y]].velocity;
+fn advect_cells(
+    cells: &mut Array2<Cell>,
+    source: &Array2<Cell>,
+    velocities: &Array2<Cell>,
+    trace: Trace,
+    delta_size: Real,
+) {
+    for_each_indexed(cells, |(x, y), cell| {
+        let source_pos = backtrace(velocities, trace, (x, y), delta_size);
+        *cell = sample_cell(source, trace.boundary, source_pos);
+    });
+}
+
+/// Limits `cell` to the range of the four `source` cells the fluid at
+/// `(x, y)` was interpolated from, so corrections can't overshoot.
+fn clamp_to_source(
+    cell: Cell,
+    source: &Array2<Cell>,
+    velocities: &Array2<Cell>,
+    trace: Trace,
+    (x, y): (usize, usize),
+    delta_size: Real,
+) -> Cell {
+    let source_pos = backtrace(velocities, trace, (x, y), delta_size);
+    let corners = sample_corners(source, trace.boundary, source_pos);
+    let (min, max) = corners[1..]
+        .iter()
+        .fold((corners[0], corners[0]), |(min, max), corner| {
+            (
+                Cell {
+                    dye: min.dye.min(corner.dye),
+                    velocity: min.velocity.min(corner.velocity),
+                    temperature: min.temperature.min(corner.temperature),
+                },
+                Cell {
+                    dye: max.dye.max(corner.dye),
+                    velocity: max.velocity.max(corner.velocity),
+                    temperature: max.temperature.max(corner.temperature),
+                },
+            )
+        });
+
+    Cell {
+        dye: cell.dye.clamp(min.dye, max.dye),
+        velocity: cell.velocity.clamp(min.velocity, max.velocity),
+        temperature: cell.temperature.clamp(min.temperature, max.temperature),
+    }
+}
+
+/// Where the fluid now at `(x, y)` came from. Without wrapping, the source
+/// stays between the centers of the outermost two rows, as in Stam's
+/// solver, so samples never mix in the edge cells from beyond a wall.
+fn backtrace(
+    velocities: &Array2<Cell>,
+    trace: Trace,
+    (x, y): (usize, usize),
+    delta_size: Real,
+) -> Vec2 {
+    let start = Vec2::new(x as Real, y as Real);
+    backtrace_from(
+        velocities,
+        trace,
+        start,
+        velocities[[x, y]].velocity,
+        delta_size,
+    )
+}
+
+/// Like `backtrace`, from `start` in cell coordinates, where the velocity is
+/// `velocity`.
+fn backtrace_from(
+    velocities: &Array2<Cell>,
+    Trace { boundary, order }: Trace,
+    start: Vec2,
+    velocity: Vec2,
+    delta_size: Real,
+) -> Vec2 {
+    let velocity = match order {
+        BacktraceOrder::Rk1 => velocity,
+        BacktraceOrder::Rk2 => {
+            let midpoint = start - 0.5 * delta_size * velocity;
+            sample_cell(velocities, boundary, midpoint).velocity
+        }
+    };
+    let mut position = start - delta_size * velocity;
+    let (width, height) = velocities.dim();
+    let clamp = |value: Real, mode, size: usize| match mode {
+        BoundaryMode::Wrap => value,
+        BoundaryMode::Reflect | BoundaryMode::Open => {
+            value.clamp(0.5, (size as Real - 1.5).max(0.5))
+        }
+    };
+    position.x = clamp(position.x, boundary.x, width);
+    position.y = clamp(position.y, boundary.y, height);
+    position
+}
+
+fn sample_corners(cells: &Array2<Cell>, boundary: Boundary, position: Vec2) -> [Cell; 4] {
+    let left_idx = position.x.floor() as isize;
+    let right_idx = left_idx + 1;
+    let top_idx = position.y.floor() as isize;
+    let bottom_idx = top_idx + 1;
+
+    [
+        get_cell(cells, boundary, left_idx, top_idx),
+        get_cell(cells, boundary, right_idx, top_idx),
+        get_cell(cells, boundary, left_idx, bottom_idx),
+        get_cell(cells, boundary, right_idx, bottom_idx),
+    ]
+}
+
+fn sample_cell(cells: &Array2<Cell>, boundary: Boundary, position: Vec2) -> Cell {
+    Cell::bilerp(
+        sample_corners(cells, boundary, position),
+        position - position.floor(),
+    )
+}
+
+/// The center of detail cell `(x, y)` in the cells' coordinates.
+fn detail_to_cell(factor: usize, x: usize, y: usize) -> Vec2 {
+    (Vec2::new(x as Real, y as Real) + 0.5) / factor as Real - 0.5
+}
+
+fn sample_dye(dye: &Array2<Vec3>, boundary: Boundary, position: Vec2) -> Vec3 {
+    let (i, j) = (position.x.floor() as isize, position.y.floor() as isize);
+    let t = position - position.floor();
+    let get = |i, j| get_scalar(dye, boundary, i, j);
+    let top = get(i, j).lerp(get(i + 1, j), t.x);
+    let bottom = get(i, j + 1).lerp(get(i + 1, j + 1), t.x);
+    top.lerp(bottom, t.y)
+}
 
-            let left_idx = source_pos.x.floor() as isize;
-            let right_idx = left_idx + 1;
-            let top_idx = source_pos.y.floor() as isize;
-            let bottom_idx = top_idx + 1;
+fn for_each_indexed<T: Send>(
+    array: &mut Array2<T>,
+    f: impl Fn((usize, usize), &mut T) + Send + Sync,
+) {
+    #[cfg(feature = "rayon")]
+    Zip::indexed(array).par_for_each(f);
+    #[cfg(not(feature = "rayon"))]
+    Zip::indexed(array).for_each(f);
+}
+
+fn relax<T: Copy + Send + Sync>(
+    values: &mut Array2<T>,
+    scratch: &mut Array2<T>,
+    update: impl Fn(&Array2<T>, usize, usize) -> T + Sync,
+) {
+    for parity in [0, 1] {
+        let current = &*values;
+        for_each_indexed(scratch, |(x, y), value| {
+            if (x + y) % 2 == parity {
+                *value = update(current, x, y);
+            }
+        });
+        Zip::indexed(&mut *values)
+            .and(&*scratch)
+            .for_each(|(x, y), value, &updated| {
+                if (x + y) % 2 == parity {
+                    *value = updated;
+                }
+            });
+    }
+}
+
+/// Sets the edge cells from their inner neighbours times the sign for that
+/// axis. Axes without a sign wrap, so their edges are left alone and the
+/// other axis's edges run all the way across.
+fn set_boundary_ring<T, V>(
+    array: &mut Array2<T>,
+    sign_x: Option<Real>,
+    sign_y: Option<Real>,
+    value: impl Fn(&mut T) -> &mut V,
+) where
+    V: Copy + Add<Output = V> + Mul<Real, Output = V>,
+{
+    let (width, height) = array.dim();
+    if width < 3 || height < 3 {
+        return;
+    }
+    let (last_x, last_y) = (width - 1, height - 1);
+    let inner = |sign: Option<Real>, last| if sign.is_some() { 1..last } else { 0..last + 1 };
 
-            let top_left = get_cell(&self.prev_cells, left_idx, top_idx);
-            let top_right = get_cell(&self.prev_cells, right_idx, top_idx);
-            let bottom_left = get_cell(&self.prev_cells, left_idx, bottom_idx);
-            let bottom_right = get_cell(&self.prev_cells, right_idx, bottom_idx);
+    if let Some(sign_x) = sign_x {
+        for y in inner(sign_y, last_y) {
+            *value(&mut array[[0, y]]) = *value(&mut array[[1, y]]) * sign_x;
+            *value(&mut array[[last_x, y]]) = *value(&mut array[[last_x - 1, y]]) * sign_x;
+        }
+    }
+    if let Some(sign_y) = sign_y {
+        for x in inner(sign_x, last_x) {
+            *value(&mut array[[x, 0]]) = *value(&mut array[[x, 1]]) * sign_y;
+            *value(&mut array[[x, last_y]]) = *value(&mut array[[x, last_y - 1]]) * sign_y;
+        }
+    }
+    if sign_x.is_none() || sign_y.is_none() {
+        return;
+    }
+
+    for (x, y, inner_x, inner_y) in [
+        (0, 0, 1, 1),
+        (last_x, 0, last_x - 1, 1),
+        (0, last_y, 1, last_y - 1),
+        (last_x, last_y, last_x - 1, last_y - 1),
+    ] {
+        *value(&mut array[[x, y]]) =
+            (*value(&mut array[[inner_x, y]]) + *value(&mut array[[x, inner_y]])) * 0.5;
+    }
+}
 
-            let right_coefficient = source_pos.x - left_idx as f32;
-            let left_coefficient = 1.0 - right_coefficient;
-            let bottom_coefficient = source_pos.y - top_idx as f32;
-            let top_coefficient = 1.0 - bottom_coefficient;
+fn get_cell(cells: &Array2<Cell>, boundary: Boundary, i: isize, j: isize) -> Cell {
+    let (x, y) = boundary.resolve(i, j, cells.dim());
+    let mut cell = cells[[x, y]];
+    if boundary.x == BoundaryMode::Reflect && x as isize != i {
+        cell.velocity.x = -cell.velocity.x;
+    }
+    if boundary.y == BoundaryMode::Reflect && y as isize != j {
+        cell.velocity.y = -cell.velocity.y;
+    }
+    cell
+}
 
-            cell.density = left_coefficient
-                * (top_coefficient * top_left.density + bottom_coefficient * bottom_left.density)
-                + right_coefficient
-                    * (top_coefficient * top_right.density
-                        + bottom_coefficient * bottom_right.density);
+fn velocity_divergence(cells: &Array2<Cell>, boundary: Boundary, i: isize, j: isize) -> Real {
+    0.5 * (get_cell(cells, boundary, i + 1, j).velocity.x
+        - get_cell(cells, boundary, i - 1, j).velocity.x
+        + get_cell(cells, boundary, i, j + 1).velocity.y
+        - get_cell(cells, boundary, i, j - 1).velocity.y)
+}
 
-            cell.velocity = left_coefficient
-                * (top_coefficient * top_left.velocity + bottom_coefficient * bottom_left.velocity)
-                + right_coefficient
-                    * (top_coefficient * top_right.velocity
-                        + bottom_coefficient * bottom_right.velocity);
+/// The operator the pressure sweeps invert: `4 p - sum of neighbours`, with
+/// solid and out-of-range neighbours mirroring the center.
+fn apply_pressure_laplacian(
+    pressure: &Array2<Real>,
+    obstacles: &Array2<bool>,
+    boundary: Boundary,
+    output: &mut Array2<Real>,
+) {
+    for_each_indexed(output, |(x, y), output| {
+        if obstacles[[x, y]] {
+            *output = pressure[[x, y]];
+            return;
         }
+
+        let i = x as isize;
+        let j = y as isize;
+        *output = 4.0 * pressure[[x, y]]
+            - get_pressure(pressure, obstacles, boundary, (x, y), i - 1, j)
+            - get_pressure(pressure, obstacles, boundary, (x, y), i + 1, j)
+            - get_pressure(pressure, obstacles, boundary, (x, y), i, j - 1)
+            - get_pressure(pressure, obstacles, boundary, (x, y), i, j + 1);
+    });
+}
+
+/// One V-cycle on `A pressure = rhs`, with `A` as in
+/// `apply_pressure_laplacian`. Coarse grids are rediscretized from the
+/// obstacle mask: a coarse cell is solid only if all the cells it covers are.
+fn v_cycle(
+    pressure: &mut Array2<Real>,
+    rhs: &Array2<Real>,
+    obstacles: &Array2<bool>,
+    boundary: Boundary,
+    levels: usize,
+) {
+    let (width, height) = pressure.dim();
+    if levels <= 1 || width.min(height) < MULTIGRID_MIN_SIZE {
+        smooth_pressure(pressure, rhs, obstacles, boundary, MULTIGRID_COARSE_SWEEPS);
+        return;
+    }
+
+    smooth_pressure(pressure, rhs, obstacles, boundary, MULTIGRID_SWEEPS);
+
+    let mut residual = Array2::zeros(pressure.raw_dim());
+    apply_pressure_laplacian(pressure, obstacles, boundary, &mut residual);
+    Zip::from(&mut residual)
+        .and(rhs)
+        .and(obstacles)
+        .for_each(|residual, &rhs, &solid| {
+            *residual = if solid { 0.0 } else { rhs - *residual };
+        });
+
+    // Halving the grid spacing quarters the operator, so the coarse right
+    // hand side is the sum of the fine residuals rather than their average.
+    let coarse_dim = (width.div_ceil(2), height.div_ceil(2));
+    let children = |x: usize, y: usize| {
+        [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .into_iter()
+            .map(move |(dx, dy)| (2 * x + dx, 2 * y + dy))
+            .filter(move |&(x, y)| x < width && y < height)
+    };
+    let coarse_obstacles = Array2::from_shape_fn(coarse_dim, |(x, y)| {
+        children(x, y).all(|child| obstacles[child])
+    });
+    let coarse_rhs = Array2::from_shape_fn(coarse_dim, |(x, y)| {
+        children(x, y).map(|child| residual[child]).sum()
+    });
+
+    let mut correction = Array2::zeros(coarse_dim);
+    v_cycle(
+        &mut correction,
+        &coarse_rhs,
+        &coarse_obstacles,
+        boundary,
+        levels - 1,
+    );
+
+    Zip::indexed(&mut *pressure)
+        .and(obstacles)
+        .for_each(|(x, y), pressure, &solid| {
+            if !solid {
+                *pressure += correction[[x / 2, y / 2]];
+            }
+        });
+
+    smooth_pressure(pressure, rhs, obstacles, boundary, MULTIGRID_SWEEPS);
+}
+
+fn smooth_pressure(
+    pressure: &mut Array2<Real>,
+    rhs: &Array2<Real>,
+    obstacles: &Array2<bool>,
+    boundary: Boundary,
+    sweeps: usize,
+) {
+    let mut scratch = pressure.clone();
+    for _ in 0..sweeps {
+        relax(pressure, &mut scratch, |pressure, x, y| {
+            if obstacles[[x, y]] {
+                return pressure[[x, y]];
+            }
+
+            let i = x as isize;
+            let j = y as isize;
+            0.25 * (rhs[[x, y]]
+                + get_pressure(pressure, obstacles, boundary, (x, y), i - 1, j)
+                + get_pressure(pressure, obstacles, boundary, (x, y), i + 1, j)
+                + get_pressure(pressure, obstacles, boundary, (x, y), i, j - 1)
+                + get_pressure(pressure, obstacles, boundary, (x, y), i, j + 1))
+        });
+    }
+}
+
+fn dot(a: &Array2<Real>, b: &Array2<Real>) -> f64 {
+    Zip::from(a)
+        .and(b)
+        .fold(0.0, |sum, &a, &b| sum + to_f64(a) * to_f64(b))
+}
+
+fn get_scalar<T: Copy>(values: &Array2<T>, boundary: Boundary, i: isize, j: isize) -> T {
+    let (x, y) = boundary.resolve(i, j, values.dim());
+    values[[x, y]]
+}
+
+fn get_pressure(
+    pressure: &Array2<Real>,
+    obstacles: &Array2<bool>,
+    boundary: Boundary,
+    center: (usize, usize),
+    i: isize,
+    j: isize,
+) -> Real {
+    if get_scalar(obstacles, boundary, i, j) {
+        pressure[center]
+    } else {
+        get_scalar(pressure, boundary, i, j)
     }
 }
 
-fn get_cell(cells: &Array2<Cell>, i: isize, j: isize) -> &Cell {
-    let x = wrap_index(i, cells.dim().0);
-    let y = wrap_index(j, cells.dim().1);
+fn get_cell_ref(cells: &Array2<Cell>, boundary: Boundary, i: isize, j: isize) -> &Cell {
+    let (x, y) = boundary.resolve(i, j, cells.dim());
     &cells[[x, y]]
 }
 
-fn get_cell_mut(cells: &mut Array2<Cell>, i: isize, j: isize) -> &mut Cell {
-    let x = wrap_index(i, cells.dim().0);
-    let y = wrap_index(j, cells.dim().1);
+fn get_cell_mut(cells: &mut Array2<Cell>, boundary: Boundary, i: isize, j: isize) -> &mut Cell {
+    let (x, y) = boundary.resolve(i, j, cells.dim());
     &mut cells[[x, y]]
 }
 
+/// Gradient of value noise at `position`, with lattice values between -1 and
+/// 1 hashed from `seed` and smoothstepped between.
+fn noise_gradient(seed: u64, position: Vec2) -> Vec2 {
+    let corner = position.floor();
+    let t = position - corner;
+    let (x, y) = (corner.x as i64, corner.y as i64);
+    let value = |dx, dy| lattice_value(seed, x + dx, y + dy);
+    let (v00, v10, v01, v11) = (value(0, 0), value(1, 0), value(0, 1), value(1, 1));
+
+    let smooth = t * t * (3.0 - 2.0 * t);
+    let slope = 6.0 * t * (1.0 - t);
+    Vec2::new(
+        slope.x * ((v10 - v00) + smooth.y * (v00 - v10 - v01 + v11)),
+        slope.y * ((v01 - v00) + smooth.x * (v00 - v10 - v01 + v11)),
+    )
+}
+
+fn lattice_value(seed: u64, x: i64, y: i64) -> Real {
+    // SplitMix64 finalizer over the seed and lattice coordinates.
+    let mut hash = seed
+        ^ (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^= hash >> 31;
+    (hash >> 40) as Real / (1u64 << 23) as Real - 1.0
+}
+
+#[cfg(not(feature = "f64"))]
+fn seconds(duration: Duration) -> Real {
+    duration.as_secs_f32()
+}
+
+#[cfg(feature = "f64")]
+fn seconds(duration: Duration) -> Real {
+    duration.as_secs_f64()
+}
+
+/// Narrows `value` for rendering and the GPU.
+#[allow(clippy::unnecessary_cast)] // `Real` is `f32` without the `f64` feature.
+pub fn to_f32(value: Real) -> f32 {
+    value as f32
+}
+
+#[allow(clippy::unnecessary_cast)] // `Real` is `f64` with the `f64` feature.
+fn to_f64(value: Real) -> f64 {
+    value as f64
+}
+
 fn wrap_index(mut index: isize, size: usize) -> usize {
     let size = size as isize;
     index %= size;
@@ -183,15 +2526,484 @@ fn wrap_index(mut index: isize, size: usize) -> usize {
     index as usize
 }
 
+fn clamp_index(index: isize, size: usize) -> usize {
+    index.clamp(0, size as isize - 1) as usize
+}
+
 impl Index<(isize, isize)> for Fluid {
     type Output = Cell;
     fn index(&self, (x, y): (isize, isize)) -> &Self::Output {
-        get_cell(&self.cells, x, y)
+        get_cell_ref(&self.cells, self.boundary, x, y)
     }
 }
 
 impl IndexMut<(isize, isize)> for Fluid {
     fn index_mut(&mut self, (x, y): (isize, isize)) -> &mut Self::Output {
-        get_cell_mut(&mut self.cells, x, y)
+        get_cell_mut(&mut self.cells, self.boundary, x, y)
+    }
+}
+
+/// Direct access to `cells` with ndarray-style `fluid[[x, y]]` indexing,
+/// without boundary handling. Panics when out of bounds; index with an
+/// `(isize, isize)` tuple for wrapped or clamped access.
+///
+/// Taking an array rather than a `(usize, usize)` tuple keeps untyped
+/// literals like `fluid[(1, 2)]` unambiguous.
+impl Index<[usize; 2]> for Fluid {
+    type Output = Cell;
+    fn index(&self, index: [usize; 2]) -> &Self::Output {
+        &self.cells[index]
+    }
+}
+
+impl IndexMut<[usize; 2]> for Fluid {
+    fn index_mut(&mut self, index: [usize; 2]) -> &mut Self::Output {
+        &mut self.cells[index]
+    }
+}
+
+/// A NaN or infinite coefficient passed to [`Fluid::set_diffusion`] or
+/// [`Fluid::set_viscosity`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InvalidCoefficient {
+    pub name: &'static str,
+    pub value: Real,
+}
+
+impl Display for InvalidCoefficient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} must be finite, got {}", self.name, self.value)
+    }
+}
+
+impl Error for InvalidCoefficient {}
+
+/// Returned by [`Fluid::with_detail`] when the density grid isn't the
+/// velocity grid scaled up by the same whole factor on both axes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MismatchedResolution {
+    pub velocity: (usize, usize),
+    pub density: (usize, usize),
+}
+
+impl Display for MismatchedResolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "density grid {}x{} isn't a whole multiple of velocity grid {}x{}",
+            self.density.0, self.density.1, self.velocity.0, self.velocity.1
+        )
+    }
+}
+
+impl Error for MismatchedResolution {}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::s;
+
+    use super::*;
+
+    #[test]
+    fn projection_keeps_uniform_flow() {
+        let velocity = Vec2::new(0.3, -0.2);
+        let mut fluid = Fluid::builder()
+            .size(32)
+            .boundary(BoundaryMode::Wrap)
+            .build();
+        fluid.init_with(|_, _| Cell {
+            velocity,
+            ..Cell::default()
+        });
+
+        fluid.project(&mut CpuPressure);
+
+        for (_, cell) in fluid.iter() {
+            assert!(cell.velocity.distance(velocity) < 1e-6, "{cell:?}");
+        }
+    }
+
+    #[test]
+    fn dye_does_not_leak_through_obstacles() {
+        let mut fluid = Fluid::builder()
+            .size(32)
+            .diffusion(1e-3)
+            .boundary(BoundaryMode::Reflect)
+            .build();
+        for y in 0..32 {
+            fluid.set_obstacle(16, y, true);
+            fluid[(15, y)].dye = Vec3::ONE;
+        }
+
+        fluid.step_n(Duration::from_millis(16), 100);
+
+        assert!(fluid[(14, 16)].density() > 0.0);
+        for ((x, _), cell) in fluid.iter() {
+            if x >= 16 {
+                assert_eq!(cell.dye, Vec3::ZERO, "dye at x = {x}");
+            }
+        }
+    }
+
+    #[test]
+    fn projection_reduces_divergence() {
+        let mut fluid = Fluid::builder()
+            .size(32)
+            .boundary(BoundaryMode::Reflect)
+            .pressure_solver(PressureSolver::ConjugateGradient {
+                tolerance: 1e-6,
+                max_iterations: 200,
+            })
+            .build();
+        // A source in the middle pushing outwards.
+        fluid.init_with(|x, y| {
+            let offset = Vec2::new(x, y) - 0.5;
+            Cell {
+                velocity: offset * Falloff::Gaussian.weight(offset.length() / 0.2),
+                ..Cell::default()
+            }
+        });
+
+        let before = fluid.max_divergence();
+        fluid.project(&mut CpuPressure);
+        let after = fluid.max_divergence();
+        assert!(after < 0.25 * before, "{before} -> {after}");
+    }
+
+    #[test]
+    fn wrap_index_wraps_into_range() {
+        assert_eq!(wrap_index(-1, 10), 9);
+        assert_eq!(wrap_index(-25, 10), 5);
+        assert_eq!(wrap_index(10, 10), 0);
+        assert_eq!(wrap_index(25, 10), 5);
+        assert_eq!(wrap_index(0, 10), 0);
+        assert_eq!(wrap_index(9, 10), 9);
+    }
+
+    #[test]
+    fn bilerp_hits_corners_and_averages_center() {
+        let corner = |value: Real| Cell {
+            dye: Vec3::new(value, 2.0 * value, -value),
+            velocity: Vec2::new(-3.0 * value, value),
+            temperature: 10.0 * value,
+        };
+        let corners = [corner(1.0), corner(2.0), corner(3.0), corner(5.0)];
+        let [top_left, top_right, bottom_left, bottom_right] = corners;
+
+        assert_eq!(Cell::bilerp(corners, Vec2::new(0.0, 0.0)), top_left);
+        assert_eq!(Cell::bilerp(corners, Vec2::new(1.0, 0.0)), top_right);
+        assert_eq!(Cell::bilerp(corners, Vec2::new(0.0, 1.0)), bottom_left);
+        assert_eq!(Cell::bilerp(corners, Vec2::new(1.0, 1.0)), bottom_right);
+
+        let center = Cell::bilerp(corners, Vec2::new(0.5, 0.5));
+        let average = corner(11.0 / 4.0);
+        assert!(center.dye.distance(average.dye) < 1e-6, "{center:?}");
+        assert!(
+            center.velocity.distance(average.velocity) < 1e-6,
+            "{center:?}"
+        );
+        assert!(
+            (center.temperature - average.temperature).abs() < 1e-6,
+            "{center:?}"
+        );
+    }
+
+    #[test]
+    fn scalar_boundary_sets_edge_dye() {
+        let edge_dye = |density_boundary| {
+            let mut fluid = Fluid::builder()
+                .size(32)
+                .diffusion(1e-4)
+                .boundary(BoundaryMode::Reflect)
+                .density_boundary(density_boundary)
+                .build();
+            fluid.init_with(|x, _| Cell {
+                dye: if x < 0.125 { Vec3::ONE } else { Vec3::ZERO },
+                ..Cell::default()
+            });
+            fluid.step_n(Duration::from_millis(16), 10);
+            (fluid[(0, 16)].density(), fluid[(1, 16)].density())
+        };
+
+        let (edge, inner) = edge_dye(ScalarBoundary::Dirichlet);
+        assert!(inner > 0.0, "{inner}");
+        assert_eq!(edge, 0.0);
+
+        let (edge, inner) = edge_dye(ScalarBoundary::Neumann);
+        assert!(inner > 0.5, "{inner}");
+        assert!((edge - inner).abs() < 1e-6, "{edge} vs {inner}");
+    }
+
+    #[test]
+    fn reflect_does_not_wrap_dye() {
+        let mut fluid = Fluid::builder()
+            .size(32)
+            .diffusion(0.0)
+            .boundary(BoundaryMode::Reflect)
+            .build();
+        fluid.init_with(|x, _| Cell {
+            dye: if x < 0.125 { Vec3::ONE } else { Vec3::ZERO },
+            velocity: Vec2::new(-1.0, 0.0),
+            ..Cell::default()
+        });
+
+        fluid.step_n(Duration::from_millis(16), 20);
+
+        for ((x, _), cell) in fluid.iter() {
+            if x >= 24 {
+                assert_eq!(cell.dye, Vec3::ZERO, "dye at x = {x}");
+            }
+        }
+    }
+
+    #[test]
+    fn per_axis_boundary_wraps_only_x() {
+        let mut fluid = Fluid::builder()
+            .size(32)
+            .diffusion(0.0)
+            .boundary(Boundary::new(BoundaryMode::Wrap, BoundaryMode::Reflect))
+            .build();
+        fluid.init_with(|x, y| Cell {
+            dye: if Vec2::new(x, y).distance(Vec2::new(0.85, 0.15)) < 0.08 {
+                Vec3::ONE
+            } else {
+                Vec3::ZERO
+            },
+            velocity: Vec2::new(1.0, -0.5),
+            ..Cell::default()
+        });
+
+        fluid.step_n(Duration::from_millis(16), 20);
+
+        let wrapped: Real = fluid
+            .iter()
+            .filter(|((x, _), _)| *x < 8)
+            .map(|(_, cell)| cell.density())
+            .sum();
+        assert!(wrapped > 1.0, "{wrapped}");
+        for ((_, y), cell) in fluid.iter() {
+            if y >= 24 {
+                assert_eq!(cell.dye, Vec3::ZERO, "dye at y = {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn rk2_tracks_rotation_better_than_rk1() {
+        // Half a revolution of solid-body rotation, which carries the blob to
+        // the opposite side of the center.
+        let center = Vec2::splat(0.5);
+        let rotation = |x: Real, y: Real| {
+            let offset = Vec2::new(x, y) - center;
+            Vec2::new(-offset.y, offset.x) * std::f64::consts::PI as Real
+        };
+        let centroid_error = |backtrace| {
+            let mut fluid = Fluid::builder()
+                .size(64)
+                .boundary(BoundaryMode::Reflect)
+                .backtrace(backtrace)
+                .build();
+            fluid.init_with(|x, y| {
+                let distance = Vec2::new(x, y).distance(Vec2::new(0.75, 0.5));
+                Cell {
+                    dye: Vec3::splat((-(distance / 0.05).powi(2) / 2.0).exp()),
+                    velocity: rotation(x, y),
+                    ..Cell::default()
+                }
+            });
+            let size = fluid.width as Real;
+            for _ in 0..50 {
+                fluid.step_coupled(
+                    Duration::from_millis(20),
+                    |fluid, _| {
+                        for ((x, y), cell) in fluid.iter_mut() {
+                            let (x, y) = ((x as Real + 0.5) / size, (y as Real + 0.5) / size);
+                            cell.velocity = rotation(x, y);
+                        }
+                    },
+                    |_, _| {},
+                );
+            }
+
+            let (weighted, total) =
+                fluid
+                    .iter()
+                    .fold((Vec2::ZERO, 0.0), |(weighted, total), ((x, y), cell)| {
+                        let position = Vec2::new(x as Real + 0.5, y as Real + 0.5) / size;
+                        (weighted + position * cell.density(), total + cell.density())
+                    });
+            (weighted / total).distance(Vec2::new(0.25, 0.5))
+        };
+
+        let rk1 = centroid_error(BacktraceOrder::Rk1);
+        let rk2 = centroid_error(BacktraceOrder::Rk2);
+        assert!(rk2 < rk1, "RK2 error {rk2} vs RK1 {rk1}");
+    }
+
+    #[test]
+    fn step_substeps_covers_delta_past_max_dt() {
+        let mut fluid = Fluid::builder()
+            .size(64)
+            .boundary(BoundaryMode::Wrap)
+            .build();
+        fluid.init_with(|x, y| {
+            let distance = Vec2::new(x, y).distance(Vec2::new(0.25, 0.5));
+            Cell {
+                dye: Vec3::splat((-(distance / 0.05).powi(2) / 2.0).exp()),
+                velocity: Vec2::new(1.0, 0.0),
+                ..Cell::default()
+            }
+        });
+        let delta = Duration::from_millis(250);
+        assert!(delta > fluid.max_dt);
+
+        // Each substep moves the dye by exactly one cell.
+        fluid.step_substeps(delta, 16);
+
+        let size = fluid.width as Real;
+        let (weighted, total) =
+            fluid
+                .iter()
+                .fold((0.0, 0.0), |(weighted, total), ((x, _), cell)| {
+                    let x = (x as Real + 0.5) / size;
+                    (weighted + x * cell.density(), total + cell.density())
+                });
+        let centroid = weighted / total;
+        assert!((centroid - 0.5).abs() < 1e-3, "centroid at {centroid}");
+    }
+
+    fn detail_fluid(factor: usize) -> Fluid {
+        Fluid::with_detail(0.0, 0.0, (16, 16), (16 * factor, 16 * factor)).unwrap()
+    }
+
+    #[test]
+    fn with_detail_rejects_mismatched_resolutions() {
+        for density in [(24, 24), (32, 48), (8, 8), (0, 0)] {
+            assert_eq!(
+                Fluid::with_detail(0.0, 0.0, (16, 16), density).unwrap_err(),
+                MismatchedResolution {
+                    velocity: (16, 16),
+                    density,
+                }
+            );
+        }
+        let fluid = Fluid::with_detail(0.0, 0.0, (16, 8), (48, 24)).unwrap();
+        assert_eq!(fluid.detail.unwrap().factor, 3);
+    }
+
+    #[test]
+    fn detail_factor_one_matches_plain_fluid() {
+        let vortex = |x: Real, y: Real| {
+            let offset = Vec2::new(x, y) - 0.5;
+            let weight = Falloff::Gaussian.weight(offset.length() / 0.25);
+            Cell {
+                dye: Vec3::new(weight, 0.5 * weight, 0.0),
+                velocity: weight * offset.perp(),
+                ..Cell::default()
+            }
+        };
+        let mut plain = Fluid::with_dimensions(1e-4, 1e-4, 32, 32);
+        let mut detailed = Fluid::with_detail(1e-4, 1e-4, (32, 32), (32, 32)).unwrap();
+        plain.init_with(vortex);
+        detailed.init_with(vortex);
+
+        plain.step_n(Duration::from_millis(16), 30);
+        detailed.step_n(Duration::from_millis(16), 30);
+
+        // Only rounding differs, about 4e-7 in f32.
+        for ((x, y), cell) in plain.iter() {
+            let difference = cell.dye.distance(detailed.cells[[x, y]].dye);
+            assert!(difference < 1e-5, "{difference} at ({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn detail_cells_hold_block_means() {
+        let mut fluid = detail_fluid(4);
+        fluid.init_with(|x, y| Cell {
+            dye: Vec3::splat((x * 37.0).sin().abs() * y),
+            velocity: Vec2::new(0.3, 0.1),
+            ..Cell::default()
+        });
+
+        fluid.step(Duration::from_millis(16));
+
+        let detail = fluid.detail.as_ref().unwrap();
+        for ((x, y), cell) in fluid.iter() {
+            let block = detail.dye.slice(s![x * 4..(x + 1) * 4, y * 4..(y + 1) * 4]);
+            let mean = block.iter().sum::<Vec3>() / 16.0;
+            assert!(cell.dye.distance(mean) < 1e-6, "at ({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn coarse_dye_changes_reach_detail() {
+        let mut fluid = detail_fluid(4);
+        fluid.init_with(|x, _| Cell {
+            dye: Vec3::splat(if x < 0.5 { x } else { 0.0 }),
+            ..Cell::default()
+        });
+        let before = fluid.detail.as_ref().unwrap().dye.current().clone();
+        fluid.splat(
+            Vec2::splat(0.75),
+            0.1,
+            Falloff::Gaussian,
+            Cell {
+                dye: Vec3::ONE,
+                ..Cell::default()
+            },
+        );
+        fluid[(2, 8)].dye *= 2.0;
+
+        // At rest, advection leaves the pushed detail where it is.
+        fluid.step(Duration::from_millis(16));
+
+        let detail = fluid.detail.as_ref().unwrap();
+        let block =
+            |x: usize, y: usize| detail.dye.slice(s![x * 4..(x + 1) * 4, y * 4..(y + 1) * 4]);
+        // A scaled block keeps its gradient.
+        let scaled = block(2, 8);
+        let original = before.slice(s![8..12, 32..36]);
+        for (&scaled, &original) in scaled.iter().zip(&original) {
+            assert!(scaled.distance(original * 2.0) < 1e-6);
+        }
+        // The splat landed in empty blocks, which fill evenly.
+        let splatted = fluid[(12, 12)].dye;
+        assert!(splatted.x > 0.5, "{splatted}");
+        for &dye in block(12, 12) {
+            assert!(dye.distance(splatted) < 1e-6, "{dye} vs {splatted}");
+        }
+    }
+
+    #[test]
+    fn detail_follows_edges_obstacles_clear_and_resize() {
+        let mut fluid = detail_fluid(4);
+        fluid.boundary = BoundaryMode::Reflect.into();
+        fluid.density_boundary = ScalarBoundary::Dirichlet;
+        fluid.init_with(|_, _| Cell {
+            dye: Vec3::ONE,
+            ..Cell::default()
+        });
+        fluid.set_obstacle(8, 8, true);
+
+        fluid.step(Duration::from_millis(16));
+
+        let detail = fluid.detail.as_ref().unwrap();
+        for ((x, y), &dye) in detail.dye.indexed_iter() {
+            let (x, y) = (x / 4, y / 4);
+            if x == 0 || y == 0 || x == 15 || y == 15 || (x, y) == (8, 8) {
+                assert_eq!(dye, Vec3::ZERO, "detail dye in cell ({x}, {y})");
+            }
+        }
+        assert!(detail.dye[[20, 20]].x > 0.5);
+
+        fluid.resize_dimensions(8, 12);
+        let detail = fluid.detail.as_ref().unwrap();
+        assert_eq!((detail.factor, detail.dye.dim()), (4, (32, 48)));
+
+        fluid.clear();
+        let detail = fluid.detail.as_ref().unwrap();
+        assert!(detail.dye.iter().all(|&dye| dye == Vec3::ZERO));
+        assert!(detail.dye.previous().iter().all(|&dye| dye == Vec3::ZERO));
     }
 }
@@ -0,0 +1,303 @@
+use std::mem;
+
+use ndarray::Array2;
+use wgpu::*;
+
+use crate::fluid::{
+    to_f32, BoundaryMode, CpuPressure, Fluid, PressureBackend, PressureSolver, Real,
+};
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Projection in compute shaders: divergence, a Jacobi pressure solve and
+/// the gradient subtraction all run on the device, with velocity and
+/// pressure kept in storage buffers between them.
+///
+/// Only `PressureSolver::GaussSeidel` runs here, as the same number of
+/// weighted Jacobi iterations: Jacobi converges more slowly per iteration,
+/// but every cell updates in parallel, so large grids can afford many more.
+/// `ConjugateGradient` and `Multigrid` are projected on the CPU instead.
+///
+/// Advection still runs on the CPU, so each projection uploads the velocity
+/// once and reads velocity and pressure back with a single blocking map.
+/// The warm-start pressure stays on the device.
+pub struct GpuPressure {
+    pub divergence_pipeline: ComputePipeline,
+    pub jacobi_pipeline: ComputePipeline,
+    pub gradient_pipeline: ComputePipeline,
+    pub params: Buffer,
+    grid: Option<GpuGrid>,
+}
+
+struct GpuGrid {
+    width: usize,
+    height: usize,
+    velocity: Buffer,
+    obstacles: Buffer,
+    /// What `obstacles` holds, so unchanged obstacles aren't uploaded again.
+    uploaded_obstacles: Option<Array2<bool>>,
+    /// Jacobi reads one and writes the other.
+    pressure: [Buffer; 2],
+    /// The last solution of each of the two projections in a substep, to
+    /// warm-start from like `Fluid::warm_start` does on the CPU.
+    warm_pressure: [Buffer; 2],
+    next_warm: usize,
+    readback: Buffer,
+    divergence_bind_group: BindGroup,
+    // `jacobi_bind_groups[n]` reads `pressure[n]` and writes the other
+    // buffer, `gradient_bind_groups[n]` reads `pressure[n]`.
+    jacobi_bind_groups: [BindGroup; 2],
+    gradient_bind_groups: [BindGroup; 2],
+}
+
+/// A [`PressureBackend`] borrowing a [`GpuPressure`] and the device it was
+/// created on.
+pub struct GpuPressureBackend<'a> {
+    pub gpu: &'a mut GpuPressure,
+    pub device: &'a Device,
+    pub queue: &'a Queue,
+}
+
+impl GpuPressure {
+    pub fn new(device: &Device) -> Self {
+        let shader = device.create_shader_module(include_wgsl!("./pressure.wgsl"));
+        // Layouts come from each entry point's bindings.
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: None,
+                layout: None,
+                module: &shader,
+                entry_point,
+            })
+        };
+
+        let params = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: 8 * mem::size_of::<u32>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            divergence_pipeline: pipeline("compute_divergence"),
+            jacobi_pipeline: pipeline("jacobi"),
+            gradient_pipeline: pipeline("subtract_gradient"),
+            params,
+            grid: None,
+        }
+    }
+
+    pub fn backend<'a>(
+        &'a mut self,
+        device: &'a Device,
+        queue: &'a Queue,
+    ) -> GpuPressureBackend<'a> {
+        GpuPressureBackend {
+            gpu: self,
+            device,
+            queue,
+        }
+    }
+
+    /// Projects `fluid`'s velocity and leaves the solution in
+    /// `fluid.pressure`. Returns `false` without touching `fluid` when its
+    /// solver isn't `GaussSeidel` or the readback fails, so the caller can
+    /// project on the CPU.
+    pub fn project(&mut self, device: &Device, queue: &Queue, fluid: &mut Fluid) -> bool {
+        let PressureSolver::GaussSeidel { iterations } = fluid.pressure_solver else {
+            return false;
+        };
+        let (width, height) = (fluid.width, fluid.height);
+        if !matches!(&self.grid, Some(grid) if grid.width == width && grid.height == height) {
+            self.grid = Some(self.create_grid(device, width, height));
+        }
+        let grid = self.grid.as_mut().unwrap();
+
+        let boundary = |mode| match mode {
+            BoundaryMode::Wrap => 0,
+            BoundaryMode::Reflect => 1,
+            BoundaryMode::Open => 2,
+        };
+        let cell_size = 1.0 / width.max(height) as f32;
+        let params: [u32; 8] = [
+            width as u32,
+            height as u32,
+            boundary(fluid.boundary.x),
+            boundary(fluid.boundary.y),
+            cell_size.to_bits(),
+            0,
+            0,
+            0,
+        ];
+        queue.write_buffer(&self.params, 0, bytemuck::cast_slice(&params));
+
+        let velocity: Vec<[f32; 2]> = fluid
+            .cells
+            .iter()
+            .map(|cell| [to_f32(cell.velocity.x), to_f32(cell.velocity.y)])
+            .collect();
+        queue.write_buffer(&grid.velocity, 0, bytemuck::cast_slice(&velocity));
+        if grid.uploaded_obstacles.as_ref() != Some(&fluid.obstacles) {
+            let obstacles: Vec<u32> = fluid.obstacles.iter().map(|&solid| solid as u32).collect();
+            queue.write_buffer(&grid.obstacles, 0, bytemuck::cast_slice(&obstacles));
+            grid.uploaded_obstacles = Some(fluid.obstacles.clone());
+        }
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        let warm = &grid.warm_pressure[grid.next_warm];
+        if fluid.warm_start {
+            encoder.copy_buffer_to_buffer(warm, 0, &grid.pressure[0], 0, warm.size());
+        } else {
+            encoder.clear_buffer(&grid.pressure[0], 0, None);
+        }
+
+        let workgroups = (
+            (width as u32).div_ceil(WORKGROUP_SIZE),
+            (height as u32).div_ceil(WORKGROUP_SIZE),
+        );
+        let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+        compute_pass.set_pipeline(&self.divergence_pipeline);
+        compute_pass.set_bind_group(0, &grid.divergence_bind_group, &[]);
+        compute_pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+        compute_pass.set_pipeline(&self.jacobi_pipeline);
+        for iteration in 0..iterations {
+            compute_pass.set_bind_group(0, &grid.jacobi_bind_groups[iteration % 2], &[]);
+            compute_pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+        }
+        compute_pass.set_pipeline(&self.gradient_pipeline);
+        compute_pass.set_bind_group(0, &grid.gradient_bind_groups[iterations % 2], &[]);
+        compute_pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+        drop(compute_pass);
+
+        let result = &grid.pressure[iterations % 2];
+        encoder.copy_buffer_to_buffer(result, 0, warm, 0, result.size());
+        let velocity_size = grid.velocity.size();
+        encoder.copy_buffer_to_buffer(&grid.velocity, 0, &grid.readback, 0, velocity_size);
+        encoder.copy_buffer_to_buffer(result, 0, &grid.readback, velocity_size, result.size());
+        queue.submit([encoder.finish()]);
+        grid.next_warm = 1 - grid.next_warm;
+
+        let slice = grid.readback.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(Maintain::Wait);
+        if !matches!(receiver.recv(), Ok(Ok(()))) {
+            return false;
+        }
+
+        let data = slice.get_mapped_range();
+        let (velocity, pressure) = data.split_at(velocity_size as usize);
+        let velocity: &[[f32; 2]] = bytemuck::cast_slice(velocity);
+        for (cell, &[x, y]) in fluid.cells.iter_mut().zip(velocity) {
+            cell.velocity.x = x as Real;
+            cell.velocity.y = y as Real;
+        }
+        let pressure: &[f32] = bytemuck::cast_slice(pressure);
+        for (pressure, &value) in fluid.pressure.iter_mut().zip(pressure) {
+            *pressure = value as Real;
+        }
+        drop(data);
+        grid.readback.unmap();
+        true
+    }
+
+    fn create_grid(&self, device: &Device, width: usize, height: usize) -> GpuGrid {
+        let cells = (width * height) as u64;
+        let scalar_size = cells * mem::size_of::<f32>() as u64;
+        let velocity_size = cells * mem::size_of::<[f32; 2]>() as u64;
+        let buffer = |size, usage| {
+            device.create_buffer(&BufferDescriptor {
+                label: None,
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let storage = BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC;
+        let copy = BufferUsages::COPY_DST | BufferUsages::COPY_SRC;
+
+        let velocity = buffer(velocity_size, storage);
+        let divergence = buffer(scalar_size, storage);
+        let obstacles = buffer(scalar_size, storage);
+        let pressure = [buffer(scalar_size, storage), buffer(scalar_size, storage)];
+        // Zeroed on creation, so the first solves start cold.
+        let warm_pressure = [buffer(scalar_size, copy), buffer(scalar_size, copy)];
+        let readback = buffer(
+            velocity_size + scalar_size,
+            BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        );
+
+        let bind_group = |pipeline: &ComputePipeline, buffers: &[(u32, &Buffer)]| {
+            let entries: Vec<_> = [(0, &self.params)]
+                .iter()
+                .chain(buffers)
+                .map(|&(binding, buffer)| BindGroupEntry {
+                    binding,
+                    resource: buffer.as_entire_binding(),
+                })
+                .collect();
+            device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.get_bind_group_layout(0),
+                entries: &entries,
+            })
+        };
+        let divergence_bind_group = bind_group(
+            &self.divergence_pipeline,
+            &[(1, &velocity), (2, &divergence)],
+        );
+        let jacobi_bind_group = |source, destination| {
+            bind_group(
+                &self.jacobi_pipeline,
+                &[
+                    (3, &divergence),
+                    (4, &obstacles),
+                    (5, source),
+                    (6, destination),
+                ],
+            )
+        };
+        let jacobi_bind_groups = [
+            jacobi_bind_group(&pressure[0], &pressure[1]),
+            jacobi_bind_group(&pressure[1], &pressure[0]),
+        ];
+        let gradient_bind_group = |source| {
+            bind_group(
+                &self.gradient_pipeline,
+                &[(4, &obstacles), (5, source), (7, &velocity)],
+            )
+        };
+        let gradient_bind_groups = [
+            gradient_bind_group(&pressure[0]),
+            gradient_bind_group(&pressure[1]),
+        ];
+
+        GpuGrid {
+            width,
+            height,
+            velocity,
+            obstacles,
+            uploaded_obstacles: None,
+            pressure,
+            warm_pressure,
+            next_warm: 0,
+            readback,
+            divergence_bind_group,
+            jacobi_bind_groups,
+            gradient_bind_groups,
+        }
+    }
+}
+
+impl PressureBackend for GpuPressureBackend<'_> {
+    /// Only reached when `project` declined, so solve on the CPU.
+    fn solve(&mut self, fluid: &mut Fluid) {
+        CpuPressure.solve(fluid);
+    }
+
+    fn project(&mut self, fluid: &mut Fluid) -> bool {
+        self.gpu.project(self.device, self.queue, fluid)
+    }
+}
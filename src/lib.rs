@@ -1,3 +1,10 @@
+pub mod colormap;
+pub mod double_buffer;
 pub mod fluid;
+pub mod gpu_pressure;
+pub mod particles;
+#[cfg(feature = "image")]
+pub mod recorder;
 pub mod renderer;
+pub mod scene;
 pub mod timer;
@@ -1,117 +1,609 @@
-use core::time;
-use std::f32;
-use std::time::Instant;
+use std::{collections::HashMap, mem, time::Duration};
 
-use eyre::Result;
+use eyre::{Result, WrapErr};
 use fluidsim::{
-    fluid::Fluid,
-    renderer::{FluidTexture, Renderer}, timer::{Timer, FpsCounter},
+    fluid::{Cell, Falloff, Fluid, Real, Vec2 as FluidVec2, Vec3},
+    particles::Particles,
+    renderer::{Camera, FluidTexture, Quiver, Renderer, RendererOptions},
+    scene::{Scenario, Scene},
+    timer::{FpsCounter, FrameStats, Instant, Timer},
 };
 use glam::Vec2;
-use winit::event::{ElementState, MouseButton};
+use winit::event::{
+    DeviceId, ElementState, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta, Touch,
+    TouchPhase, VirtualKeyCode,
+};
 use winit::{
-    dpi::{LogicalPosition, LogicalSize},
+    dpi::{LogicalPosition, LogicalSize, PhysicalPosition},
     event::{Event, WindowEvent},
     event_loop::EventLoop,
-    window::WindowBuilder,
+    window::{Window, WindowBuilder},
 };
 
 const WINDOW_SIZE: u32 = 800;
 const RESOLUTION: usize = 200;
 const BRUSH_RADIUS: f32 = 0.1;
-const BRUSH_DENSITY: f32 = 1.0;
+const BRUSH_RADIUS_RANGE: (f32, f32) = (0.01, 0.5);
+const BRUSH_RADIUS_SCROLL_FACTOR: f32 = 1.1;
+const BRUSH_DENSITY: Real = 1.0;
+const BRUSH_DENSITY_RANGE: (Real, Real) = (0.125, 8.0);
+const BRUSH_FALLOFF: Falloff = Falloff::Gaussian;
+const BRUSH_COLORS: [Vec3; 5] = [
+    Vec3::new(1.0, 1.0, 1.0),
+    Vec3::new(1.0, 0.3, 0.1),
+    Vec3::new(0.1, 0.6, 1.0),
+    Vec3::new(0.2, 1.0, 0.3),
+    Vec3::new(1.0, 0.9, 0.1),
+];
+const BRUSH_TEMPERATURE: Real = 1.0;
+/// Time constant of the low-pass filter on the cursor velocity. Longer
+/// smooths out jitter and flick spikes at the cost of lag.
+const CURSOR_SMOOTHING: Duration = Duration::from_millis(50);
+const BUOYANCY_ALPHA: Real = 0.0;
+const BUOYANCY_BETA: Real = 0.5;
+const FIXED_DT: Duration = Duration::from_micros(16_667);
+/// Pan per key press, as a fraction of the visible area.
+const PAN_STEP: f32 = 0.1;
+const ZOOM_FACTOR: f32 = 1.25;
+const COEFFICIENT_MIN: Real = 1e-6;
+const COEFFICIENT_MAX: Real = 1e-1;
+const STEP_STATS_CAPACITY: usize = 120;
+const TITLE_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+const PARTICLES_PER_SIDE: usize = 64;
+
+const USAGE: &str = "usage: fluidsim [--resolution CELLS] [--window PIXELS] \
+[--viscosity VALUE] [--diffusion VALUE] [--preview CELLS] [--deterministic] [--gpu-pressure]";
+
+#[derive(Debug, Clone, Copy)]
+struct Args {
+    resolution: usize,
+    window_size: u32,
+    viscosity: Real,
+    diffusion: Real,
+    /// Resolution to drop to while painting, so the brush stays responsive
+    /// on grids too large to step at the frame rate.
+    preview_resolution: Option<usize>,
+    /// Advance by `FIXED_DT` every frame instead of the measured frame time,
+    /// so the same input always gives the same run.
+    deterministic: bool,
+    /// Project on the GPU when the adapter supports compute shaders.
+    gpu_pressure: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            resolution: RESOLUTION,
+            window_size: WINDOW_SIZE,
+            viscosity: 0.0,
+            diffusion: 0.0,
+            preview_resolution: None,
+            deterministic: false,
+            gpu_pressure: false,
+        }
+    }
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut parsed = Args::default();
+        while let Some(flag) = args.next() {
+            if flag == "--help" || flag == "-h" {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            if flag == "--deterministic" {
+                parsed.deterministic = true;
+                continue;
+            }
+            if flag == "--gpu-pressure" {
+                parsed.gpu_pressure = true;
+                continue;
+            }
+
+            let value = args
+                .next()
+                .ok_or_else(|| eyre::eyre!("missing value for {flag}\n{USAGE}"))?;
+            let invalid = || format!("invalid value {value:?} for {flag}");
+            match flag.as_str() {
+                "--resolution" => parsed.resolution = value.parse().wrap_err_with(invalid)?,
+                "--window" => parsed.window_size = value.parse().wrap_err_with(invalid)?,
+                "--viscosity" => parsed.viscosity = value.parse().wrap_err_with(invalid)?,
+                "--diffusion" => parsed.diffusion = value.parse().wrap_err_with(invalid)?,
+                "--preview" => {
+                    parsed.preview_resolution = Some(value.parse().wrap_err_with(invalid)?)
+                }
+                _ => eyre::bail!("unknown argument {flag}\n{USAGE}"),
+            }
+        }
+        if parsed.resolution == 0 || parsed.window_size == 0 || parsed.preview_resolution == Some(0)
+        {
+            eyre::bail!("resolution, window size and preview resolution must be positive");
+        }
+        Ok(parsed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BrushMode {
+    Dye,
+    Velocity,
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum PointerId {
+    Mouse(DeviceId),
+    Touch(DeviceId, u64),
+}
+
+/// A mouse or finger, in normalized window coordinates.
+#[derive(Debug, Clone, Copy, Default)]
+struct Pointer {
+    position: Vec2,
+    velocity: Vec2,
+    painting: bool,
+    placing_obstacles: bool,
+}
+
+impl Pointer {
+    /// Moves to `position`, low-pass filtering the velocity implied by the
+    /// move.
+    fn move_to(&mut self, position: Vec2, delta: Duration) {
+        let delta = delta.as_secs_f32();
+        if delta > 0.0 {
+            let raw_velocity = (position - self.position) / delta;
+            let blend = 1.0 - (-delta / CURSOR_SMOOTHING.as_secs_f32()).exp();
+            self.velocity = self.velocity.lerp(raw_velocity, blend);
+        }
+        self.position = position;
+    }
+}
 
 async fn run() -> Result<()> {
+    let args = Args::parse(std::env::args().skip(1))?;
+
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
-        .with_inner_size(LogicalSize::new(WINDOW_SIZE, WINDOW_SIZE))
+        .with_title("fluidsim")
+        .with_inner_size(LogicalSize::new(args.window_size, args.window_size))
         .build(&event_loop)?;
 
-    let fluid = Fluid::new(0.0, 0.0, RESOLUTION);
+    #[cfg(target_arch = "wasm32")]
+    attach_canvas(&window)?;
+
+    let mut fluid = Fluid::builder()
+        .size(args.resolution)
+        .fixed_dt(FIXED_DT)
+        .buoyancy(BUOYANCY_ALPHA, BUOYANCY_BETA)
+        .build();
+    fluid.set_diffusion(args.diffusion)?;
+    fluid.set_viscosity(args.viscosity)?;
 
-    let renderer = Renderer::new(window).await?;
+    let options = RendererOptions {
+        gpu_pressure: args.gpu_pressure,
+        ..Default::default()
+    };
+    let mut renderer = Renderer::with_options(window, options).await?;
 
     let mut fluid_texture = FluidTexture::new(fluid, &renderer);
 
-    let mut timer = Timer::new();
+    let mut timer = if args.deterministic {
+        Timer::fixed(FIXED_DT)
+    } else {
+        Timer::new()
+    };
     let mut fps_counter = FpsCounter::new();
+    let mut step_stats = FrameStats::new(STEP_STATS_CAPACITY);
+    let mut last_title_update = Instant::now();
 
-    let mut cursor_position = Vec2::ZERO;
-    let mut cursor_velocity = Vec2::ZERO;
-    let mut button_pressed = false;
+    let mut pointers = HashMap::<PointerId, Pointer>::new();
+    let mut brush_mode = BrushMode::Both;
+    let mut brush_radius = BRUSH_RADIUS;
+    let mut brush_density = BRUSH_DENSITY;
+    let mut brush_color = 0;
+    let mut paused = false;
+    let mut single_step = false;
+    let mut modifiers = ModifiersState::empty();
 
     event_loop.run(move |event, _, control| {
-        let delta = timer.delta();
+        let delta = timer.delta().min(fluid_texture.fluid.max_dt);
 
         match event {
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::CloseRequested => control.set_exit(),
-                WindowEvent::CursorMoved { position, .. } => {
-                    let logical_position = position.to_logical(renderer.window.scale_factor());
-                    let normalized_pos = window_to_normalized(logical_position);
-                    cursor_velocity = (normalized_pos - cursor_position) / delta.as_secs_f32();
-                    cursor_position = normalized_pos;
+                WindowEvent::Resized(new_size) => renderer.resize(new_size),
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    renderer.resize(*new_inner_size)
                 }
-                WindowEvent::MouseInput { button, state, .. } => {
-                    if button == MouseButton::Left {
-                        button_pressed = state == ElementState::Pressed;
+                WindowEvent::CursorMoved {
+                    device_id,
+                    position,
+                    ..
+                } => {
+                    let position = fluid_texture
+                        .camera
+                        .window_to_fluid(physical_to_normalized(&renderer.window, position));
+                    pointers
+                        .entry(PointerId::Mouse(device_id))
+                        .or_insert(Pointer {
+                            position,
+                            ..Default::default()
+                        })
+                        .move_to(position, delta);
+                }
+                WindowEvent::CursorLeft { device_id } => {
+                    pointers.remove(&PointerId::Mouse(device_id));
+                }
+                WindowEvent::MouseInput {
+                    device_id,
+                    button,
+                    state,
+                    ..
+                } => {
+                    let pointer = pointers.entry(PointerId::Mouse(device_id)).or_default();
+                    let pressed = state == ElementState::Pressed;
+                    match button {
+                        MouseButton::Left => pointer.painting = pressed,
+                        MouseButton::Right => pointer.placing_obstacles = pressed,
+                        _ => {}
                     }
                 }
+                WindowEvent::Touch(Touch {
+                    device_id,
+                    phase,
+                    location,
+                    id,
+                    ..
+                }) => {
+                    let id = PointerId::Touch(device_id, id);
+                    let position = fluid_texture
+                        .camera
+                        .window_to_fluid(physical_to_normalized(&renderer.window, location));
+                    match phase {
+                        TouchPhase::Started => {
+                            pointers.insert(
+                                id,
+                                Pointer {
+                                    position,
+                                    painting: true,
+                                    ..Default::default()
+                                },
+                            );
+                        }
+                        TouchPhase::Moved => {
+                            if let Some(pointer) = pointers.get_mut(&id) {
+                                pointer.move_to(position, delta);
+                            }
+                        }
+                        TouchPhase::Ended | TouchPhase::Cancelled => {
+                            pointers.remove(&id);
+                        }
+                    }
+                }
+                WindowEvent::MouseWheel { delta, .. } => {
+                    let lines = match delta {
+                        MouseScrollDelta::LineDelta(_, y) => y,
+                        MouseScrollDelta::PixelDelta(position) => position.y as f32 / 100.0,
+                    };
+                    if modifiers.ctrl() {
+                        let camera = &mut fluid_texture.camera;
+                        camera.zoom *= ZOOM_FACTOR.powf(lines);
+                        camera.clamp();
+                    } else {
+                        let (min, max) = BRUSH_RADIUS_RANGE;
+                        brush_radius =
+                            (brush_radius * BRUSH_RADIUS_SCROLL_FACTOR.powf(lines)).clamp(min, max);
+                    }
+                }
+                WindowEvent::ModifiersChanged(new_modifiers) => modifiers = new_modifiers,
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(keycode),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => match keycode {
+                    VirtualKeyCode::M => {
+                        fluid_texture.mode = fluid_texture.mode.next();
+                        println!("render mode: {:?}", fluid_texture.mode);
+                    }
+                    VirtualKeyCode::C => {
+                        fluid_texture.colormap = fluid_texture.colormap.next();
+                        println!("colormap: {:?}", fluid_texture.colormap);
+                    }
+                    VirtualKeyCode::X => {
+                        // The texture rebinds its sampler to match on the
+                        // next update.
+                        let fluid = &mut fluid_texture.fluid;
+                        fluid.boundary = fluid.boundary.x.next().into();
+                        println!("boundary: {:?}", fluid.boundary.x);
+                    }
+                    VirtualKeyCode::F1
+                    | VirtualKeyCode::F2
+                    | VirtualKeyCode::F3
+                    | VirtualKeyCode::F4 => {
+                        let scenario = match keycode {
+                            VirtualKeyCode::F1 => Scenario::Plume,
+                            VirtualKeyCode::F2 => Scenario::ShearLayer,
+                            VirtualKeyCode::F3 => Scenario::VortexPair,
+                            _ => Scenario::Cylinder,
+                        };
+                        let fluid = mem::replace(&mut fluid_texture.fluid, Fluid::new(0.0, 0.0, 0));
+                        let mut scene = Scene::new(fluid);
+                        scenario.setup(&mut scene);
+                        fluid_texture.fluid = scene.fluid;
+                        println!("scenario: {scenario:?}");
+                    }
+                    VirtualKeyCode::V => {
+                        let quiver = &mut fluid_texture.quiver;
+                        *quiver = match quiver {
+                            Some(_) => None,
+                            None => Some(Quiver::default()),
+                        };
+                        println!("quiver: {}", if quiver.is_some() { "on" } else { "off" });
+                    }
+                    VirtualKeyCode::T => {
+                        let particles = &mut fluid_texture.particles;
+                        if particles.is_empty() {
+                            *particles = Particles::grid(PARTICLES_PER_SIDE);
+                        } else {
+                            particles.clear();
+                        }
+                        println!("particles: {}", particles.len());
+                    }
+                    VirtualKeyCode::Space => {
+                        paused = !paused;
+                        println!("{}", if paused { "paused" } else { "resumed" });
+                    }
+                    VirtualKeyCode::Right if paused => single_step = true,
+                    VirtualKeyCode::R => fluid_texture.fluid.clear(),
+                    VirtualKeyCode::Up | VirtualKeyCode::Down => {
+                        let (min, max) = BRUSH_DENSITY_RANGE;
+                        let factor = if keycode == VirtualKeyCode::Up {
+                            2.0
+                        } else {
+                            0.5
+                        };
+                        brush_density = (brush_density * factor).clamp(min, max);
+                        println!("brush density: {brush_density}");
+                    }
+                    VirtualKeyCode::B => {
+                        brush_color = (brush_color + 1) % BRUSH_COLORS.len();
+                        println!("brush color: {}", BRUSH_COLORS[brush_color]);
+                    }
+                    VirtualKeyCode::LBracket | VirtualKeyCode::RBracket => {
+                        let fluid = &mut fluid_texture.fluid;
+                        let increase = keycode == VirtualKeyCode::RBracket;
+                        match fluid.set_viscosity(adjust_coefficient(fluid.viscosity, increase)) {
+                            Ok(()) => println!("viscosity: {}", fluid.viscosity),
+                            Err(err) => eprintln!("{err}"),
+                        }
+                    }
+                    VirtualKeyCode::Minus | VirtualKeyCode::Equals => {
+                        let fluid = &mut fluid_texture.fluid;
+                        let increase = keycode == VirtualKeyCode::Equals;
+                        match fluid.set_diffusion(adjust_coefficient(fluid.diffusion, increase)) {
+                            Ok(()) => println!("diffusion: {}", fluid.diffusion),
+                            Err(err) => eprintln!("{err}"),
+                        }
+                    }
+                    VirtualKeyCode::Key1 | VirtualKeyCode::Key2 | VirtualKeyCode::Key3 => {
+                        brush_mode = match keycode {
+                            VirtualKeyCode::Key1 => BrushMode::Dye,
+                            VirtualKeyCode::Key2 => BrushMode::Velocity,
+                            _ => BrushMode::Both,
+                        };
+                        println!("brush mode: {brush_mode:?}");
+                    }
+                    VirtualKeyCode::W
+                    | VirtualKeyCode::A
+                    | VirtualKeyCode::S
+                    | VirtualKeyCode::D
+                    | VirtualKeyCode::Q
+                    | VirtualKeyCode::E => {
+                        let camera = &mut fluid_texture.camera;
+                        let pan = PAN_STEP / camera.zoom;
+                        match keycode {
+                            VirtualKeyCode::W => camera.offset.y += pan,
+                            VirtualKeyCode::A => camera.offset.x -= pan,
+                            VirtualKeyCode::S => camera.offset.y -= pan,
+                            VirtualKeyCode::D => camera.offset.x += pan,
+                            VirtualKeyCode::Q => camera.zoom /= ZOOM_FACTOR,
+                            _ => camera.zoom *= ZOOM_FACTOR,
+                        }
+                        camera.clamp();
+                    }
+                    VirtualKeyCode::Key0 => fluid_texture.camera = Camera::default(),
+                    #[cfg(all(feature = "image", not(target_arch = "wasm32")))]
+                    VirtualKeyCode::P => match save_screenshot(&renderer, &fluid_texture) {
+                        Ok(path) => println!("saved {path}"),
+                        Err(err) => eprintln!("screenshot failed: {err}"),
+                    },
+                    _ => {}
+                },
                 _ => {}
             },
             Event::MainEventsCleared => {
                 timer.tick();
 
-                if button_pressed {
-                    let cell_radius = (BRUSH_RADIUS * RESOLUTION as f32 / 2.0).ceil() as isize;
-                    let (cursor_cell_x, cursor_cell_y) = normalized_to_cell(cursor_position);
-
-                    for i in (cursor_cell_x - cell_radius)..=(cursor_cell_x + cell_radius) {
-                        for j in (cursor_cell_y - cell_radius)..=(cursor_cell_y + cell_radius) {
-                            let normalized_pos = cell_to_normalized(i, j);
-                            if normalized_pos.distance_squared(cursor_position)
-                                < BRUSH_RADIUS as f32 * BRUSH_RADIUS as f32
-                            {
-                                let cell = &mut fluid_texture.fluid[(i, j)];
-                                cell.density += BRUSH_DENSITY * delta.as_secs_f32();
-                                cell.velocity += cursor_velocity;
-                            }
+                if let Some(preview_resolution) = args.preview_resolution {
+                    let painting = pointers.values().any(|pointer| pointer.painting);
+                    let resolution = if painting {
+                        preview_resolution
+                    } else {
+                        args.resolution
+                    };
+                    // The texture follows the new size on its next update,
+                    // and linear sampling upsamples the coarse grid.
+                    if fluid_texture.fluid.width != resolution {
+                        fluid_texture.fluid.resize(resolution);
+                    }
+                }
+
+                for pointer in pointers
+                    .values()
+                    .filter(|pointer| pointer.placing_obstacles)
+                {
+                    for (i, j) in
+                        brush_cells(pointer.position, brush_radius, fluid_texture.fluid.width)
+                    {
+                        fluid_texture.fluid.set_obstacle(i, j, true);
+                    }
+                }
+
+                if !paused || single_step {
+                    let delta = if paused { FIXED_DT } else { delta };
+
+                    for pointer in pointers.values().filter(|pointer| pointer.painting) {
+                        let mut amount = Cell::default();
+                        if brush_mode != BrushMode::Velocity {
+                            let seconds = delta.as_secs_f32() as Real;
+                            amount.dye = BRUSH_COLORS[brush_color] * brush_density * seconds;
+                            amount.temperature = BRUSH_TEMPERATURE * seconds;
+                        }
+                        if brush_mode != BrushMode::Dye {
+                            amount.velocity = to_fluid(pointer.velocity);
                         }
+                        fluid_texture.fluid.splat(
+                            to_fluid(pointer.position / 2.0 + 0.5),
+                            (brush_radius / 2.0) as Real,
+                            BRUSH_FALLOFF,
+                            amount,
+                        );
                     }
+
+                    let step_start = Instant::now();
+                    if single_step {
+                        renderer.step_fluid(&mut fluid_texture.fluid, delta);
+                        single_step = false;
+                    } else {
+                        renderer.advance_fluid(&mut fluid_texture.fluid, delta);
+                    }
+                    fluid_texture.particles.advance(&fluid_texture.fluid, delta);
+                    step_stats.push(step_start.elapsed());
                 }
 
-                fluid_texture.fluid.step(delta);
+                renderer.reload_shader();
                 fluid_texture.update(&renderer);
                 if let Err(err) = renderer.render(&fluid_texture) {
                     eprintln!("{err}");
+                    control.set_exit();
                 }
 
                 fps_counter.add_frame();
-                println!("{} FPS", fps_counter.fps());
+                if last_title_update.elapsed() >= TITLE_UPDATE_INTERVAL {
+                    let step_time = step_stats.avg().unwrap_or_default();
+                    renderer.window.set_title(&format!(
+                        "fluidsim - {:.0} FPS, step {:.1} ms - brush radius {:.2}, density {}",
+                        fps_counter.fps(),
+                        step_time.as_secs_f32() * 1000.0,
+                        brush_radius,
+                        brush_density,
+                    ));
+                    last_title_update = Instant::now();
+                }
             }
             _ => {}
         }
     })
 }
 
-fn window_to_normalized(position: LogicalPosition<f32>) -> Vec2 {
+/// Writes the window contents to `screenshot_<unix millis>.png` in the
+/// working directory, returning the file name.
+#[cfg(all(feature = "image", not(target_arch = "wasm32")))]
+fn save_screenshot(renderer: &Renderer, fluid_texture: &FluidTexture) -> Result<String> {
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_millis();
+    let path = format!("screenshot_{millis}.png");
+    renderer
+        .screenshot(fluid_texture)?
+        .save_with_format(&path, image::ImageFormat::Png)?;
+    Ok(path)
+}
+
+/// Doubles or halves `value`, snapping to zero below `COEFFICIENT_MIN` so the
+/// coefficient can be switched off entirely.
+fn adjust_coefficient(value: Real, increase: bool) -> Real {
+    if increase {
+        (value * 2.0).clamp(COEFFICIENT_MIN, COEFFICIENT_MAX)
+    } else if value / 2.0 < COEFFICIENT_MIN {
+        0.0
+    } else {
+        value / 2.0
+    }
+}
+
+fn brush_cells(
+    center: Vec2,
+    radius: f32,
+    resolution: usize,
+) -> impl Iterator<Item = (isize, isize)> {
+    let cell_radius = (radius * resolution as f32 / 2.0).ceil() as isize;
+    let (center_x, center_y) = normalized_to_cell(center, resolution);
+
+    ((center_x - cell_radius)..=(center_x + cell_radius))
+        .flat_map(move |i| {
+            ((center_y - cell_radius)..=(center_y + cell_radius)).map(move |j| (i, j))
+        })
+        .filter(move |&(i, j)| {
+            cell_to_normalized(i, j, resolution).distance_squared(center) < radius * radius
+        })
+}
+
+/// Converts a window-space vector to the fluid's precision.
+fn to_fluid(vector: Vec2) -> FluidVec2 {
+    FluidVec2::new(vector.x as Real, vector.y as Real)
+}
+
+fn physical_to_normalized(window: &Window, position: PhysicalPosition<f64>) -> Vec2 {
+    let scale_factor = window.scale_factor();
+    window_to_normalized(
+        position.to_logical(scale_factor),
+        window.inner_size().to_logical(scale_factor),
+    )
+}
+
+fn window_to_normalized(position: LogicalPosition<f32>, size: LogicalSize<f32>) -> Vec2 {
     Vec2::new(
-        position.x / WINDOW_SIZE as f32 * 2.0 - 1.0,
-        -position.y / WINDOW_SIZE as f32 * 2.0 + 1.0,
+        position.x / size.width * 2.0 - 1.0,
+        -position.y / size.height * 2.0 + 1.0,
     )
 }
 
-fn cell_to_normalized(i: isize, j: isize) -> Vec2 {
-    Vec2::new(i as f32, j as f32) / RESOLUTION as f32 * 2.0 - 1.0
+fn cell_to_normalized(i: isize, j: isize, resolution: usize) -> Vec2 {
+    Vec2::new(i as f32, j as f32) / resolution as f32 * 2.0 - 1.0
 }
 
-fn normalized_to_cell(position: Vec2) -> (isize, isize) {
+fn normalized_to_cell(position: Vec2, resolution: usize) -> (isize, isize) {
     (
-        ((position.x / 2.0 + 0.5) * RESOLUTION as f32) as isize,
-        ((position.y / 2.0 + 0.5) * RESOLUTION as f32) as isize,
+        ((position.x / 2.0 + 0.5) * resolution as f32) as isize,
+        ((position.y / 2.0 + 0.5) * resolution as f32) as isize,
     )
 }
 
+#[cfg(target_arch = "wasm32")]
+fn attach_canvas(window: &winit::window::Window) -> Result<()> {
+    use winit::platform::web::WindowExtWebSys;
+
+    web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.body())
+        .and_then(|body| body.append_child(&window.canvas()).ok())
+        .ok_or_else(|| eyre::eyre!("couldn't append the canvas to the document body"))?;
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 fn main() {
     futures::executor::block_on(run()).expect("failure");
 }
+
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+    wasm_bindgen_futures::spawn_local(async { run().await.expect("failure") });
+}
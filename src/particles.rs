@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use crate::fluid::{BoundaryMode, Fluid, Real, Vec2};
+
+/// Massless markers carried along by a fluid's velocity, for showing how it
+/// flows. Positions are in normalized coordinates from 0 to 1, like
+/// `Fluid::sample_velocity` takes.
+#[derive(Debug, Clone, Default)]
+pub struct Particles {
+    pub positions: Vec<Vec2>,
+}
+
+impl Particles {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// `per_side` by `per_side` particles spread evenly over the fluid.
+    pub fn grid(per_side: usize) -> Self {
+        let spacing = 1.0 / per_side as Real;
+        let positions = (0..per_side)
+            .flat_map(|x| (0..per_side).map(move |y| (x, y)))
+            .map(|(x, y)| (Vec2::new(x as Real, y as Real) + 0.5) * spacing)
+            .collect();
+        Self { positions }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.positions.clear();
+    }
+
+    /// Moves every particle along `fluid`'s velocity for `delta` with a
+    /// midpoint (RK2) step. Particles wrap around axes with
+    /// `BoundaryMode::Wrap` and stop at the edges of the others.
+    pub fn advance(&mut self, fluid: &Fluid, delta: Duration) {
+        let delta = delta.as_secs_f64() as Real;
+        // Velocities are in fluid lengths per second along the longer side.
+        let size = Vec2::new(fluid.width as Real, fluid.height as Real);
+        let velocity_scale = size.max_element() / size;
+        let velocity = |position| fluid.sample_velocity(position) * velocity_scale;
+
+        let constrain = |value: Real, mode| match mode {
+            BoundaryMode::Wrap => value.rem_euclid(1.0),
+            BoundaryMode::Reflect | BoundaryMode::Open => value.clamp(0.0, 1.0),
+        };
+        for position in &mut self.positions {
+            let midpoint = *position + 0.5 * delta * velocity(*position);
+            *position += delta * velocity(midpoint);
+            position.x = constrain(position.x, fluid.boundary.x);
+            position.y = constrain(position.y, fluid.boundary.y);
+        }
+    }
+}
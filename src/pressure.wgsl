@@ -0,0 +1,154 @@
+struct Params {
+    width: u32,
+    height: u32,
+    boundary_x: u32,
+    boundary_y: u32,
+    // One over the longer side, the `h` of the CPU projection.
+    cell_size: f32,
+}
+
+const BOUNDARY_WRAP: u32 = 0u;
+const BOUNDARY_REFLECT: u32 = 1u;
+// Plain Jacobi never damps the checkerboard mode on a periodic grid, so blend
+// each update with the previous iterate.
+const WEIGHT: f32 = 0.8;
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+// Each entry point binds only what it uses, staying within the downlevel
+// limit of four storage buffers per stage.
+@group(0) @binding(1)
+var<storage, read> velocity_in: array<vec2<f32>>;
+@group(0) @binding(2)
+var<storage, read_write> divergence_out: array<f32>;
+@group(0) @binding(3)
+var<storage, read> divergence: array<f32>;
+@group(0) @binding(4)
+var<storage, read> obstacles: array<u32>;
+@group(0) @binding(5)
+var<storage, read> pressure_in: array<f32>;
+@group(0) @binding(6)
+var<storage, read_write> pressure_out: array<f32>;
+@group(0) @binding(7)
+var<storage, read_write> velocity: array<vec2<f32>>;
+
+fn resolve_index(index: i32, size: u32, boundary: u32) -> i32 {
+    let size = i32(size);
+    // Stencils only reach one cell out of range. `%` on negative operands
+    // isn't portable across backends, so wrap explicitly.
+    if boundary == BOUNDARY_WRAP {
+        if index < 0 {
+            return index + size;
+        }
+        if index >= size {
+            return index - size;
+        }
+        return index;
+    }
+    return clamp(index, 0, size - 1);
+}
+
+// Same layout as the `Array2` on the CPU: x is the outer axis.
+fn cell_index(i: i32, j: i32) -> u32 {
+    let x = resolve_index(i, params.width, params.boundary_x);
+    let y = resolve_index(j, params.height, params.boundary_y);
+    return u32(x) * params.height + u32(y);
+}
+
+// On axes that don't wrap, `set_bnd` keeps the outermost cells at copies of
+// the ones inside them. Reading pressure through to those cells instead
+// solves the same system the CPU does.
+fn inner_index(index: i32, size: u32, boundary: u32) -> i32 {
+    if boundary == BOUNDARY_WRAP || params.width < 3u || params.height < 3u {
+        return index;
+    }
+    return clamp(index, 1, i32(size) - 2);
+}
+
+fn pressure_index(i: i32, j: i32) -> u32 {
+    let x = resolve_index(i, params.width, params.boundary_x);
+    let y = resolve_index(j, params.height, params.boundary_y);
+    let inner_x = inner_index(x, params.width, params.boundary_x);
+    let inner_y = inner_index(y, params.height, params.boundary_y);
+    return u32(inner_x) * params.height + u32(inner_y);
+}
+
+// Like `get_cell`: samples past a reflecting edge flip the velocity normal
+// to it.
+fn velocity_at(i: i32, j: i32) -> vec2<f32> {
+    let x = resolve_index(i, params.width, params.boundary_x);
+    let y = resolve_index(j, params.height, params.boundary_y);
+    var value = velocity_in[u32(x) * params.height + u32(y)];
+    if params.boundary_x == BOUNDARY_REFLECT && x != i {
+        value.x = -value.x;
+    }
+    if params.boundary_y == BOUNDARY_REFLECT && y != j {
+        value.y = -value.y;
+    }
+    return value;
+}
+
+// Solid neighbours mirror the center pressure, so no flow crosses them.
+fn neighbor(center: f32, i: i32, j: i32) -> f32 {
+    let index = cell_index(i, j);
+    if obstacles[index] != 0u {
+        return center;
+    }
+    return pressure_in[pressure_index(i, j)];
+}
+
+@compute @workgroup_size(8, 8)
+fn compute_divergence(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.height {
+        return;
+    }
+
+    let i = i32(id.x);
+    let j = i32(id.y);
+    divergence_out[cell_index(i, j)] = -params.cell_size * 0.5 * (velocity_at(i + 1, j).x
+        - velocity_at(i - 1, j).x
+        + velocity_at(i, j + 1).y
+        - velocity_at(i, j - 1).y);
+}
+
+@compute @workgroup_size(8, 8)
+fn jacobi(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.height {
+        return;
+    }
+
+    let i = i32(id.x);
+    let j = i32(id.y);
+    let index = cell_index(i, j);
+    let source = pressure_index(i, j);
+    if source != index {
+        pressure_out[index] = pressure_in[source];
+        return;
+    }
+    let center = pressure_in[index];
+
+    let jacobi = 0.25 * (divergence[index]
+        + neighbor(center, i - 1, j)
+        + neighbor(center, i + 1, j)
+        + neighbor(center, i, j - 1)
+        + neighbor(center, i, j + 1));
+    pressure_out[index] = mix(center, jacobi, WEIGHT);
+}
+
+@compute @workgroup_size(8, 8)
+fn subtract_gradient(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.height {
+        return;
+    }
+
+    let i = i32(id.x);
+    let j = i32(id.y);
+    let index = cell_index(i, j);
+    let center = pressure_in[pressure_index(i, j)];
+
+    let gradient = 0.5 * vec2(
+        neighbor(center, i + 1, j) - neighbor(center, i - 1, j),
+        neighbor(center, i, j + 1) - neighbor(center, i, j - 1),
+    );
+    velocity[index] -= gradient / params.cell_size;
+}
@@ -0,0 +1,53 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use eyre::Result;
+use image::imageops::{self, FilterType};
+
+use crate::{colormap::Colormap, fluid::Fluid};
+
+/// Steps a [`Fluid`] headlessly and writes every frame to
+/// `output_dir/frame_0000.png`, `frame_0001.png`, ...
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    pub steps: usize,
+    pub dt: Duration,
+    pub output_dir: PathBuf,
+    pub colormap: Colormap,
+    /// Size of the written images; `None` writes one pixel per cell.
+    pub resolution: Option<(u32, u32)>,
+}
+
+impl Recorder {
+    pub fn new(steps: usize, dt: Duration, output_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            steps,
+            dt,
+            output_dir: output_dir.into(),
+            colormap: Colormap::default(),
+            resolution: None,
+        }
+    }
+
+    /// Runs `steps` steps of `fluid`, calling `progress(frame, steps)` after
+    /// each frame is written.
+    pub fn record(&self, fluid: &mut Fluid, mut progress: impl FnMut(usize, usize)) -> Result<()> {
+        fs::create_dir_all(&self.output_dir)?;
+
+        for frame in 0..self.steps {
+            fluid.step(self.dt);
+
+            let mut image = fluid.to_image(self.colormap);
+            if let Some((width, height)) = self.resolution {
+                image = imageops::resize(&image, width, height, FilterType::Triangle);
+            }
+            image.save_with_format(
+                self.output_dir.join(format!("frame_{frame:04}.png")),
+                image::ImageFormat::Png,
+            )?;
+
+            progress(frame + 1, self.steps);
+        }
+
+        Ok(())
+    }
+}
@@ -1,15 +1,35 @@
-use std::{error::Error, fmt::Display, iter, mem};
+use std::{
+    error::Error,
+    fmt::Display,
+    iter, mem,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use eyre::Result;
-use glam::Vec2;
-use ndarray::Axis;
+use glam::{Vec2, Vec3, Vec4};
+use ndarray::{Array2, Axis};
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
     *,
 };
-use winit::window::Window;
+use winit::{dpi::PhysicalSize, window::Window};
+
+use crate::{
+    colormap::{Colormap, LUT_SIZE, OBSTACLE_COLOR, SPEED_TINT},
+    fluid::{to_f32, Boundary, BoundaryMode, Cell, Fluid, Real},
+    gpu_pressure::GpuPressure,
+    particles::Particles,
+};
 
-use crate::fluid::Fluid;
+const QUIVER_COLORMAP: Colormap = Colormap::Viridis;
+/// Arrow heads as a fraction of the arrow's length, and their angle from the
+/// shaft in radians.
+const ARROW_HEAD_LENGTH: f32 = 0.3;
+const ARROW_HEAD_ANGLE: f32 = 0.5;
+/// Where `Renderer::reload_shader` reads the shader from in debug builds.
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+const SHADER_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shader.wgsl");
 
 pub struct Renderer {
     pub instance: Instance,
@@ -20,168 +40,696 @@ pub struct Renderer {
     pub device: Device,
     pub queue: Queue,
     pub pipeline: RenderPipeline,
+    /// Draws `FluidTexture::particles` as points.
+    pub particle_pipeline: RenderPipeline,
+    /// Draws `FluidTexture::quiver` as lines.
+    pub quiver_pipeline: RenderPipeline,
     pub sampler: Sampler,
+    /// Like `sampler`, but repeating, for fluids with `BoundaryMode::Wrap`.
+    pub repeat_sampler: Sampler,
+    /// Like `sampler`, but repeating along x only.
+    pub repeat_x_sampler: Sampler,
+    /// Like `sampler`, but repeating along y only.
+    pub repeat_y_sampler: Sampler,
     pub bind_group_layout: BindGroupLayout,
     pub quad: Buffer,
+    /// Set when `RendererOptions::gpu_pressure` asked for it and the adapter
+    /// can run compute shaders. `None` steps the fluid entirely on the CPU.
+    pub gpu_pressure: Option<GpuPressure>,
+    /// What the window shows outside the fluid quad, and through it when
+    /// `FluidTexture::density_alpha` is set and the pipeline blends.
+    pub clear_color: Color,
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    shader_watch: ShaderWatch,
+}
+
+/// What `Renderer::reload_shader` needs to rebuild the pipelines when
+/// `SHADER_PATH` changes.
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+struct ShaderWatch {
+    /// `None` until the first check, so it always reads the file once.
+    modified: Option<std::time::SystemTime>,
+    blend: BlendState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    #[default]
+    Density,
+    Velocity,
+    Pressure,
+    Curl,
+    Divergence,
+    /// Density shaded by local speed: fast smoke brightens and shifts
+    /// toward `SPEED_TINT`, still smoke keeps its dye color.
+    SpeedTint,
+}
+
+impl RenderMode {
+    pub fn next(self) -> Self {
+        match self {
+            RenderMode::Density => RenderMode::Velocity,
+            RenderMode::Velocity => RenderMode::Pressure,
+            RenderMode::Pressure => RenderMode::Curl,
+            RenderMode::Curl => RenderMode::Divergence,
+            RenderMode::Divergence => RenderMode::SpeedTint,
+            RenderMode::SpeedTint => RenderMode::Density,
+        }
+    }
+}
+
+/// Zoom and pan of the fluid quad, in normalized coordinates running from -1
+/// to 1 across both the window and the fluid. `offset` is the fluid position
+/// shown at the center of the window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub offset: Vec2,
+    pub zoom: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            offset: Vec2::ZERO,
+            zoom: 1.0,
+        }
+    }
+}
+
+impl Camera {
+    pub fn window_to_fluid(&self, position: Vec2) -> Vec2 {
+        position / self.zoom + self.offset
+    }
+
+    /// Limits zoom to at least 1 and keeps the window inside the fluid.
+    pub fn clamp(&mut self) {
+        self.zoom = self.zoom.max(1.0);
+        let extent = 1.0 - 1.0 / self.zoom;
+        self.offset = self.offset.clamp(Vec2::splat(-extent), Vec2::splat(extent));
+    }
+}
+
+/// How `FluidTexture::update` gets the frame's texels to the GPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextureUpload {
+    /// `Queue::write_texture`, which copies the texels into a staging
+    /// buffer of its own every frame.
+    #[default]
+    WriteTexture,
+    /// Writes into a pool of mapped buffers and copies from those, which
+    /// saves the allocation and lets the CPU fill one buffer while the GPU
+    /// copies another.
+    MappedBuffer,
+}
+
+/// A grid of arrows showing the fluid's velocity, colored and scaled by
+/// speed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quiver {
+    /// Cells between arrows along each axis.
+    pub stride: usize,
+    /// Length of the fastest cell's arrow, in strides.
+    pub scale: f32,
+}
+
+impl Default for Quiver {
+    fn default() -> Self {
+        Self {
+            stride: 8,
+            scale: 1.0,
+        }
+    }
+}
+
+impl Quiver {
+    /// Line list vertices of the arrows, each a normalized position and a
+    /// color.
+    fn vertices(&self, fluid: &Fluid) -> Vec<[f32; 5]> {
+        let stride = self.stride.max(1);
+        let max_speed = max_speed(fluid);
+        let size = Vec2::new(fluid.width as f32, fluid.height as f32);
+        let mut vertices = Vec::new();
+        for x in (stride / 2..fluid.width).step_by(stride) {
+            for y in (stride / 2..fluid.height).step_by(stride) {
+                let cell = fluid.cells[[x, y]];
+                let speed = to_f32(cell.velocity.length() / max_speed);
+                if fluid.obstacles[[x, y]] || speed == 0.0 {
+                    continue;
+                }
+
+                // Build the arrow in cells so it isn't squashed on
+                // rectangular grids.
+                let direction =
+                    Vec2::new(to_f32(cell.velocity.x), to_f32(cell.velocity.y)).normalize_or_zero();
+                let tail = Vec2::new(x as f32, y as f32) + 0.5;
+                let tip = tail + direction * speed * self.scale * stride as f32;
+                let head_length = (tip - tail) * ARROW_HEAD_LENGTH;
+                let left = tip - Vec2::from_angle(ARROW_HEAD_ANGLE).rotate(head_length);
+                let right = tip - Vec2::from_angle(-ARROW_HEAD_ANGLE).rotate(head_length);
+
+                let color = QUIVER_COLORMAP.sample(speed);
+                for point in [tail, tip, tip, left, tip, right] {
+                    let point = point / size;
+                    vertices.push([point.x, point.y, color.x, color.y, color.z]);
+                }
+            }
+        }
+        vertices
+    }
 }
 
 pub struct FluidTexture {
     pub fluid: Fluid,
+    pub mode: RenderMode,
+    pub colormap: Colormap,
+    pub camera: Camera,
+    /// Output density as alpha in `RenderMode::Density` and `SpeedTint`, so
+    /// smoke composites over `Renderer::clear_color` with a blending
+    /// `RendererOptions::blend`.
+    pub density_alpha: bool,
+    pub upload: TextureUpload,
+    /// Drawn as points over the fluid. Advancing them is up to the caller.
+    pub particles: Particles,
+    /// Draws a velocity arrow over the fluid every `Quiver::stride` cells.
+    pub quiver: Option<Quiver>,
     pub texture: Texture,
+    pub colormap_texture: Texture,
+    pub params: Buffer,
     pub bind_group: BindGroup,
+    texture_view: TextureView,
+    colormap_view: TextureView,
+    bound_boundary: Boundary,
+    staging: Vec<[u8; 4]>,
+    uploaded_colormap: Option<Colormap>,
+    /// Vertex buffers for the overlays, with how many vertices the last
+    /// `update` wrote to them.
+    particle_vertices: (Option<Buffer>, u32),
+    quiver_vertices: (Option<Buffer>, u32),
+    uploader: TextureUploader,
 }
 
 impl FluidTexture {
     pub fn new(fluid: Fluid, renderer: &Renderer) -> Self {
-        let texture = renderer.device.create_texture(&TextureDescriptor {
+        let texture = create_fluid_texture(renderer, (fluid.width as u32, fluid.height as u32));
+        let texture_view = texture.create_view(&Default::default());
+
+        let colormap_texture = renderer.device.create_texture(&TextureDescriptor {
             label: None,
             size: Extent3d {
-                width: fluid.size as u32,
-                height: fluid.size as u32,
+                width: LUT_SIZE as u32,
+                height: 1,
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: TextureFormat::R8Unorm,
+            format: TextureFormat::Rgba8Unorm,
             usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
         });
 
-        let texture_view = texture.create_view(&Default::default());
+        let colormap_view = colormap_texture.create_view(&Default::default());
 
-        let bind_group = renderer.device.create_bind_group(&BindGroupDescriptor {
+        let params = renderer.device.create_buffer(&BufferDescriptor {
             label: None,
-            layout: &renderer.bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&renderer.sampler),
-                },
-            ],
+            size: mem::size_of::<[u32; 8]>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
 
-        let this = Self {
+        let bind_group = create_bind_group(
+            renderer,
+            &texture_view,
+            &colormap_view,
+            &params,
+            fluid.boundary,
+        );
+
+        let mut this = Self {
+            bound_boundary: fluid.boundary,
             fluid,
+            mode: RenderMode::Density,
+            colormap: Colormap::Dye,
+            camera: Camera::default(),
+            density_alpha: false,
             texture,
+            colormap_texture,
+            params,
             bind_group,
+            texture_view,
+            colormap_view,
+            staging: Vec::new(),
+            uploaded_colormap: None,
+            particles: Particles::new(),
+            quiver: None,
+            particle_vertices: (None, 0),
+            quiver_vertices: (None, 0),
+            upload: TextureUpload::default(),
+            uploader: TextureUploader::default(),
         };
         this.update(renderer);
         this
     }
 
-    pub fn update(&self, renderer: &Renderer) {
-        let densities: Vec<_> = self
-            .fluid
-            .cells
-            .axis_iter(Axis(1))
-            .flatten()
-            .map(|cell| (cell.density * u8::MAX as f32) as u8)
+    pub fn update(&mut self, renderer: &Renderer) {
+        let size = self.texture_size();
+        let resized = (self.texture.width(), self.texture.height()) != size;
+        if resized {
+            self.texture = create_fluid_texture(renderer, size);
+            self.texture_view = self.texture.create_view(&Default::default());
+        }
+
+        if resized || self.bound_boundary != self.fluid.boundary {
+            self.bind_group = create_bind_group(
+                renderer,
+                &self.texture_view,
+                &self.colormap_view,
+                &self.params,
+                self.fluid.boundary,
+            );
+            self.bound_boundary = self.fluid.boundary;
+        }
+
+        self.staging.clear();
+        match self.mode {
+            RenderMode::Density if self.fluid.detail.is_some() => {
+                let detail = self.fluid.detail.as_ref().unwrap();
+                let factor = detail.factor;
+                let (width, height) = detail.dye.dim();
+                for y in 0..height {
+                    for x in 0..width {
+                        if self.fluid.obstacles[[x / factor, y / factor]] {
+                            self.staging.push(OBSTACLE_COLOR);
+                            continue;
+                        }
+                        let cell = Cell {
+                            dye: detail.dye[[x, y]],
+                            ..Cell::default()
+                        };
+                        self.staging.push(to_rgba8(Vec4::new(
+                            to_f32(cell.dye.x),
+                            to_f32(cell.dye.y),
+                            to_f32(cell.dye.z),
+                            to_f32(cell.density()),
+                        )));
+                    }
+                }
+            }
+            RenderMode::Density => self.staging.extend(
+                self.fluid
+                    .cells
+                    .axis_iter(Axis(1))
+                    .flatten()
+                    .zip(self.fluid.obstacles.axis_iter(Axis(1)).flatten())
+                    .map(|(cell, &solid)| {
+                        if solid {
+                            return OBSTACLE_COLOR;
+                        }
+                        let dye = cell.dye;
+                        to_rgba8(Vec4::new(
+                            to_f32(dye.x),
+                            to_f32(dye.y),
+                            to_f32(dye.z),
+                            to_f32(cell.density()),
+                        ))
+                    }),
+            ),
+            RenderMode::Velocity => {
+                let max_speed = max_speed(&self.fluid);
+                self.staging
+                    .extend(self.fluid.cells.axis_iter(Axis(1)).flatten().map(|cell| {
+                        let velocity = cell.velocity / max_speed;
+                        to_rgba8(Vec4::new(
+                            to_f32(velocity.x) * 0.5 + 0.5,
+                            to_f32(velocity.y) * 0.5 + 0.5,
+                            to_f32(velocity.length()),
+                            1.0,
+                        ))
+                    }));
+            }
+            RenderMode::Pressure => extend_signed(&mut self.staging, &self.fluid.pressure),
+            RenderMode::Curl => extend_signed(&mut self.staging, &self.fluid.curl_field()),
+            RenderMode::Divergence => {
+                extend_signed(&mut self.staging, &self.fluid.divergence_field())
+            }
+            RenderMode::SpeedTint => {
+                let max_speed = max_speed(&self.fluid);
+                self.staging.extend(
+                    self.fluid
+                        .cells
+                        .axis_iter(Axis(1))
+                        .flatten()
+                        .zip(self.fluid.obstacles.axis_iter(Axis(1)).flatten())
+                        .map(|(cell, &solid)| {
+                            if solid {
+                                return OBSTACLE_COLOR;
+                            }
+                            let speed = to_f32(cell.velocity.length() / max_speed);
+                            let density = to_f32(cell.density());
+                            let dye = Vec3::new(
+                                to_f32(cell.dye.x),
+                                to_f32(cell.dye.y),
+                                to_f32(cell.dye.z),
+                            );
+                            let color = dye.lerp(SPEED_TINT * density, speed) * (1.0 + speed);
+                            to_rgba8(color.extend(density))
+                        }),
+                );
+            }
+        }
+
+        renderer.queue.write_buffer(
+            &self.params,
+            0,
+            bytemuck::cast_slice(&[
+                self.mode as u32,
+                (self.colormap != Colormap::Dye) as u32,
+                self.density_alpha as u32,
+                0,
+                self.camera.offset.x.to_bits(),
+                self.camera.offset.y.to_bits(),
+                self.camera.zoom.to_bits(),
+                0,
+            ]),
+        );
+
+        if self.uploaded_colormap != Some(self.colormap) {
+            renderer.queue.write_texture(
+                ImageCopyTexture {
+                    texture: &self.colormap_texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                bytemuck::cast_slice(&self.colormap.lut()),
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some((mem::size_of::<[u8; 4]>() * LUT_SIZE) as u32),
+                    rows_per_image: Some(1),
+                },
+                Extent3d {
+                    width: LUT_SIZE as u32,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+            self.uploaded_colormap = Some(self.colormap);
+        }
+
+        self.uploader.upload(
+            &renderer.device,
+            &renderer.queue,
+            &self.texture,
+            &self.staging,
+            self.upload,
+        );
+
+        let positions: Vec<[f32; 2]> = self
+            .particles
+            .positions
+            .iter()
+            .map(|position| [to_f32(position.x), to_f32(position.y)])
             .collect();
+        write_vertices(renderer, &mut self.particle_vertices, &positions);
 
-        renderer.queue.write_texture(
+        let arrows = match self.quiver {
+            Some(quiver) => quiver.vertices(&self.fluid),
+            None => Vec::new(),
+        };
+        write_vertices(renderer, &mut self.quiver_vertices, &arrows);
+    }
+
+    /// The detail grid's size when showing density of a fluid with one, the
+    /// cells' otherwise.
+    fn texture_size(&self) -> (u32, u32) {
+        let (width, height) = match (&self.fluid.detail, self.mode) {
+            (Some(detail), RenderMode::Density) => detail.dye.dim(),
+            _ => (self.fluid.width, self.fluid.height),
+        };
+        (width as u32, height as u32)
+    }
+}
+
+/// Sends texels to a texture as a [`TextureUpload`] says, keeping the
+/// buffers `TextureUpload::MappedBuffer` reuses from one call to the next.
+#[derive(Debug, Default)]
+pub struct TextureUploader {
+    /// Mapped staging buffers ready for `TextureUpload::MappedBuffer`.
+    free_buffers: Arc<Mutex<Vec<Arc<Buffer>>>>,
+}
+
+impl TextureUploader {
+    /// Copies `texels`, row by row, over the whole of `texture`.
+    pub fn upload(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        texture: &Texture,
+        texels: &[[u8; 4]],
+        method: TextureUpload,
+    ) {
+        let (width, height) = (texture.width(), texture.height());
+        match method {
+            TextureUpload::WriteTexture => queue.write_texture(
+                ImageCopyTexture {
+                    texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                bytemuck::cast_slice(texels),
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(mem::size_of::<[u8; 4]>() as u32 * width),
+                    rows_per_image: Some(height),
+                },
+                Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            ),
+            TextureUpload::MappedBuffer => self.upload_mapped(device, queue, texture, texels),
+        }
+    }
+
+    /// Fills a mapped staging buffer with `texels` and copies it into
+    /// `texture`. Buffers are mapped again once the GPU is done with them, so
+    /// filling the next frame's doesn't wait on this frame's copy.
+    fn upload_mapped(&self, device: &Device, queue: &Queue, texture: &Texture, texels: &[[u8; 4]]) {
+        let (width, height) = (texture.width(), texture.height());
+        // Buffer copies need rows padded to a multiple of 256 bytes.
+        let row_bytes = width as usize * mem::size_of::<[u8; 4]>();
+        let padded_row_bytes = row_bytes.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT as usize)
+            * COPY_BYTES_PER_ROW_ALIGNMENT as usize;
+        let size = (padded_row_bytes * height as usize) as u64;
+
+        // Run the callbacks of finished maps so their buffers are free.
+        device.poll(Maintain::Poll);
+        let free = {
+            let mut free = self.free_buffers.lock().unwrap();
+            // Buffers from before a resize are the wrong size for good.
+            free.retain(|buffer| buffer.size() == size);
+            free.pop()
+        };
+        let buffer = free.unwrap_or_else(|| {
+            Arc::new(device.create_buffer(&BufferDescriptor {
+                label: None,
+                size,
+                usage: BufferUsages::MAP_WRITE | BufferUsages::COPY_SRC,
+                mapped_at_creation: true,
+            }))
+        });
+
+        {
+            let mut mapped = buffer.slice(..).get_mapped_range_mut();
+            let rows = texels.chunks_exact(width as usize);
+            for (padded_row, row) in mapped.chunks_exact_mut(padded_row_bytes).zip(rows) {
+                padded_row[..row_bytes].copy_from_slice(bytemuck::cast_slice(row));
+            }
+        }
+        buffer.unmap();
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_texture(
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes as u32),
+                    rows_per_image: Some(height),
+                },
+            },
             ImageCopyTexture {
-                texture: &self.texture,
+                texture,
                 mip_level: 0,
                 origin: Origin3d::ZERO,
                 aspect: wgpu::TextureAspect::All,
             },
-            bytemuck::cast_slice(&densities),
-            ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some((mem::size_of::<u8>() * self.fluid.size) as u32),
-                rows_per_image: Some(self.fluid.size as u32),
-            },
             Extent3d {
-                width: self.fluid.size as u32,
-                height: self.fluid.size as u32,
+                width,
+                height,
                 depth_or_array_layers: 1,
             },
         );
+        queue.submit(iter::once(encoder.finish()));
+
+        let free_buffers = Arc::clone(&self.free_buffers);
+        let mapped_buffer = Arc::clone(&buffer);
+        buffer.slice(..).map_async(MapMode::Write, move |result| {
+            if result.is_ok() {
+                free_buffers.lock().unwrap().push(mapped_buffer);
+            }
+        });
     }
 }
 
-impl Renderer {
-    pub async fn new(window: Window) -> Result<Self> {
-        let instance = Instance::new(Default::default());
-
-        let surface = unsafe { instance.create_surface(&window) }?;
-
-        let adapter = instance
-            .request_adapter(&RequestAdapterOptions {
-                compatible_surface: Some(&surface),
-                ..Default::default()
-            })
-            .await
-            .ok_or(NoAdapter)?;
-
-        let (device, queue) = adapter.request_device(&Default::default(), None).await?;
+/// Uploads `vertices`, growing the buffer when it's too small but never
+/// shrinking it.
+fn write_vertices<T: bytemuck::Pod>(
+    renderer: &Renderer,
+    (buffer, count): &mut (Option<Buffer>, u32),
+    vertices: &[T],
+) {
+    *count = vertices.len() as u32;
+    if vertices.is_empty() {
+        return;
+    }
 
-        let surface_capabilities = surface.get_capabilities(&adapter);
-        let surface_config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_capabilities.formats[0],
-            width: window.inner_size().width,
-            height: window.inner_size().height,
-            present_mode: surface_capabilities.present_modes[0],
-            alpha_mode: surface_capabilities.alpha_modes[0],
-            view_formats: vec![],
-        };
-        surface.configure(&device, &surface_config);
+    let size = mem::size_of_val(vertices) as u64;
+    if !matches!(buffer, Some(buffer) if buffer.size() >= size) {
+        *buffer = Some(renderer.device.create_buffer(&BufferDescriptor {
+            label: None,
+            size,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+    }
+    if let Some(buffer) = buffer {
+        renderer
+            .queue
+            .write_buffer(buffer, 0, bytemuck::cast_slice(vertices));
+    }
+}
 
-        let sampler = device.create_sampler(&SamplerDescriptor {
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+fn create_fluid_texture(renderer: &Renderer, (width, height): (u32, u32)) -> Texture {
+    renderer.device.create_texture(&TextureDescriptor {
+        label: None,
+        size: Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: TextureFormat::Rgba8Unorm,
+        usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
 
-        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: None,
-            entries: &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
+/// The fluid texture, its sampler, the params and the colormap, as bound by
+/// `create_bind_group`.
+fn create_bind_group_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
                 },
-                BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
                 },
-            ],
-        });
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ],
+    })
+}
 
-        let shader = device.create_shader_module(include_wgsl!("./shader.wgsl"));
+/// Compiles WGSL `source`, returning its errors instead of passing them to
+/// the device's handler, which panics.
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+fn compile_shader(device: &Device, source: &str) -> Result<ShaderModule, wgpu::Error> {
+    catch_validation(device, || {
+        device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("shader.wgsl"),
+            source: ShaderSource::Wgsl(source.into()),
+        })
+    })
+}
 
-        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            bind_group_layouts: &[&bind_group_layout],
-            ..Default::default()
-        });
+/// Runs `create` in a validation error scope and returns the first error it
+/// raised, if any.
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+fn catch_validation<T>(device: &Device, create: impl FnOnce() -> T) -> Result<T, wgpu::Error> {
+    device.push_error_scope(ErrorFilter::Validation);
+    let value = create();
+    match futures::executor::block_on(device.pop_error_scope()) {
+        Some(err) => Err(err),
+        None => Ok(value),
+    }
+}
 
-        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+/// The fluid, particle and quiver pipelines, in that order.
+fn create_pipelines(
+    device: &Device,
+    shader: &ShaderModule,
+    bind_group_layout: &BindGroupLayout,
+    format: TextureFormat,
+    blend: BlendState,
+) -> [RenderPipeline; 3] {
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        bind_group_layouts: &[bind_group_layout],
+        ..Default::default()
+    });
+
+    let create_pipeline = |vertex_entry, fragment_entry, topology, attributes: &[_]| {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
             label: None,
             layout: Some(&pipeline_layout),
             vertex: VertexState {
-                module: &shader,
-                entry_point: "vs_main",
+                module: shader,
+                entry_point: vertex_entry,
                 buffers: &[VertexBufferLayout {
-                    array_stride: 2 * mem::size_of::<f32>() as u64,
-                    attributes: &vertex_attr_array![0 => Float32x2],
+                    array_stride: attributes
+                        .iter()
+                        .map(|attribute: &VertexAttribute| attribute.format.size())
+                        .sum(),
+                    attributes,
                     step_mode: wgpu::VertexStepMode::Vertex,
                 }],
             },
             primitive: PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
+                topology,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
                 cull_mode: None,
@@ -196,20 +744,265 @@ impl Renderer {
                 alpha_to_coverage_enabled: false,
             },
             fragment: Some(FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
+                module: shader,
+                entry_point: fragment_entry,
                 targets: &[Some(ColorTargetState {
-                    format: surface_config.format,
-                    blend: Some(BlendState {
-                        color: BlendComponent::REPLACE,
-                        alpha: BlendComponent::REPLACE,
-                    }),
+                    format,
+                    blend: Some(blend),
                     write_mask: ColorWrites::ALL,
                 })],
             }),
             multiview: None,
+        })
+    };
+    let position = vertex_attr_array![0 => Float32x2];
+    let pipeline = create_pipeline(
+        "vs_main",
+        "fs_main",
+        PrimitiveTopology::TriangleList,
+        &position,
+    );
+    let particle_pipeline = create_pipeline(
+        "vs_particle",
+        "fs_particle",
+        PrimitiveTopology::PointList,
+        &position,
+    );
+    let quiver_pipeline = create_pipeline(
+        "vs_quiver",
+        "fs_quiver",
+        PrimitiveTopology::LineList,
+        &vertex_attr_array![0 => Float32x2, 1 => Float32x3],
+    );
+    [pipeline, particle_pipeline, quiver_pipeline]
+}
+
+fn create_bind_group(
+    renderer: &Renderer,
+    texture_view: &TextureView,
+    colormap_view: &TextureView,
+    params: &Buffer,
+    boundary: Boundary,
+) -> BindGroup {
+    // Repeat so linear filtering blends across the seams the fluid wraps over.
+    let sampler = match (
+        boundary.x == BoundaryMode::Wrap,
+        boundary.y == BoundaryMode::Wrap,
+    ) {
+        (true, true) => &renderer.repeat_sampler,
+        (true, false) => &renderer.repeat_x_sampler,
+        (false, true) => &renderer.repeat_y_sampler,
+        (false, false) => &renderer.sampler,
+    };
+
+    renderer.device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &renderer.bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(texture_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: params.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(colormap_view),
+            },
+        ],
+    })
+}
+
+/// The fastest cell speed, never zero so callers can divide by it.
+fn max_speed(fluid: &Fluid) -> Real {
+    fluid
+        .cells
+        .iter()
+        .map(|cell| cell.velocity.length())
+        .fold(Real::EPSILON, Real::max)
+}
+
+/// Encodes `values` normalized to -1..1 by their largest magnitude, for the
+/// shader's diverging colors.
+fn extend_signed(staging: &mut Vec<[u8; 4]>, values: &Array2<Real>) {
+    let max_value = values
+        .iter()
+        .map(|value| value.abs())
+        .fold(Real::EPSILON, Real::max);
+    staging.extend(values.axis_iter(Axis(1)).flatten().map(|&value| {
+        let value = to_f32(value / max_value) * 0.5 + 0.5;
+        to_rgba8(Vec4::new(value, value, value, 1.0))
+    }));
+}
+
+fn supported_present_mode(
+    capabilities: &SurfaceCapabilities,
+    present_mode: PresentMode,
+) -> PresentMode {
+    let automatic = matches!(
+        present_mode,
+        PresentMode::AutoVsync | PresentMode::AutoNoVsync
+    );
+    if automatic || capabilities.present_modes.contains(&present_mode) {
+        present_mode
+    } else {
+        PresentMode::Fifo
+    }
+}
+
+fn to_rgba8(color: Vec4) -> [u8; 4] {
+    let color = color * u8::MAX as f32;
+    [color.x as u8, color.y as u8, color.z as u8, color.w as u8]
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RendererOptions {
+    pub backends: Backends,
+    pub power_preference: PowerPreference,
+    /// How the fluid texture is filtered when drawn: `Nearest` shows crisp
+    /// cells, `Linear` smooth gradients.
+    pub filter: FilterMode,
+    pub clear_color: Color,
+    /// Blending of the fluid quad over the clear color. Use
+    /// `BlendState::ALPHA_BLENDING` with `FluidTexture::density_alpha`.
+    pub blend: BlendState,
+    /// `Fifo` waits for vsync, `Mailbox` and `Immediate` don't. Falls back
+    /// to `Fifo`, which every surface supports.
+    pub present_mode: PresentMode,
+    /// Projects with `GpuPressure` in `step_fluid` and `advance_fluid`, if
+    /// the adapter can run compute shaders. Off by default: it only speeds
+    /// up large grids, and solves `GaussSeidel` as Jacobi.
+    pub gpu_pressure: bool,
+}
+
+impl Default for RendererOptions {
+    fn default() -> Self {
+        // The browser build only has the WebGL2 backend.
+        #[cfg(target_arch = "wasm32")]
+        let backends = Backends::GL;
+        #[cfg(not(target_arch = "wasm32"))]
+        let backends = Backends::all();
+
+        Self {
+            backends,
+            power_preference: PowerPreference::HighPerformance,
+            filter: FilterMode::Linear,
+            clear_color: Color::TRANSPARENT,
+            blend: BlendState::REPLACE,
+            present_mode: PresentMode::Fifo,
+            gpu_pressure: false,
+        }
+    }
+}
+
+impl Renderer {
+    pub async fn new(window: Window) -> Result<Self> {
+        Self::with_options(window, Default::default()).await
+    }
+
+    pub async fn with_options(window: Window, options: RendererOptions) -> Result<Self> {
+        let instance = Instance::new(InstanceDescriptor {
+            backends: options.backends,
+            ..Default::default()
         });
 
+        let surface = unsafe { instance.create_surface(&window) }?;
+
+        let adapter = instance
+            .request_adapter(&RequestAdapterOptions {
+                power_preference: options.power_preference,
+                compatible_surface: Some(&surface),
+                ..Default::default()
+            })
+            .await
+            .ok_or(NoAdapter)?;
+
+        // Only ask for what the pipelines use so WebGL and low-end adapters
+        // qualify, but allow textures as large as the adapter supports.
+        let compute = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(DownlevelFlags::COMPUTE_SHADERS);
+        let limits = if compute {
+            Limits::downlevel_defaults()
+        } else {
+            Limits::downlevel_webgl2_defaults()
+        };
+        let (device, queue) = adapter
+            .request_device(
+                &DeviceDescriptor {
+                    label: None,
+                    features: Features::empty(),
+                    limits: limits.using_resolution(adapter.limits()),
+                },
+                None,
+            )
+            .await?;
+
+        let surface_capabilities = surface.get_capabilities(&adapter);
+        // Some headless and virtual adapters report a surface they can't
+        // present to at all.
+        let unsupported = |missing| UnsupportedSurface { missing };
+        if surface_capabilities.present_modes.is_empty() {
+            return Err(unsupported("present modes").into());
+        }
+        // The shader outputs linear color, so an sRGB surface shows the
+        // intended brightness.
+        let format = surface_capabilities
+            .formats
+            .iter()
+            .copied()
+            .find(TextureFormat::is_srgb)
+            .or_else(|| surface_capabilities.formats.first().copied())
+            .ok_or_else(|| unsupported("texture formats"))?;
+        let alpha_mode = *surface_capabilities
+            .alpha_modes
+            .first()
+            .ok_or_else(|| unsupported("alpha modes"))?;
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: window.inner_size().width,
+            height: window.inner_size().height,
+            present_mode: supported_present_mode(&surface_capabilities, options.present_mode),
+            alpha_mode,
+            view_formats: vec![],
+        };
+        surface.configure(&device, &surface_config);
+
+        let create_sampler = |address_mode_u, address_mode_v| {
+            device.create_sampler(&SamplerDescriptor {
+                address_mode_u,
+                address_mode_v,
+                mag_filter: options.filter,
+                min_filter: options.filter,
+                ..Default::default()
+            })
+        };
+        let sampler = create_sampler(AddressMode::ClampToEdge, AddressMode::ClampToEdge);
+        let repeat_sampler = create_sampler(AddressMode::Repeat, AddressMode::Repeat);
+        let repeat_x_sampler = create_sampler(AddressMode::Repeat, AddressMode::ClampToEdge);
+        let repeat_y_sampler = create_sampler(AddressMode::ClampToEdge, AddressMode::Repeat);
+
+        let bind_group_layout = create_bind_group_layout(&device);
+
+        // Debug builds swap in the file on disk with `reload_shader`.
+        let shader = device.create_shader_module(include_wgsl!("./shader.wgsl"));
+
+        let [pipeline, particle_pipeline, quiver_pipeline] = create_pipelines(
+            &device,
+            &shader,
+            &bind_group_layout,
+            surface_config.format,
+            options.blend,
+        );
+
         const VERTICES: &[Vec2] = &[
             Vec2::new(1.0, 1.0),
             Vec2::new(-1.0, 1.0),
@@ -225,6 +1018,8 @@ impl Renderer {
             usage: BufferUsages::VERTEX,
         });
 
+        let gpu_pressure = (compute && options.gpu_pressure).then(|| GpuPressure::new(&device));
+
         Ok(Self {
             window,
             instance,
@@ -234,24 +1029,219 @@ impl Renderer {
             device,
             queue,
             pipeline,
+            particle_pipeline,
+            quiver_pipeline,
             sampler,
+            repeat_sampler,
+            repeat_x_sampler,
+            repeat_y_sampler,
             bind_group_layout,
             quad,
+            gpu_pressure,
+            clear_color: options.clear_color,
+            #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+            shader_watch: ShaderWatch {
+                modified: None,
+                blend: options.blend,
+            },
         })
     }
 
+    /// In debug builds, rebuilds the pipelines from `src/shader.wgsl` on disk
+    /// when it changed since the last call, so visuals can be tweaked without
+    /// recompiling. A shader that doesn't compile prints its errors and
+    /// leaves the current pipelines in place. Release and web builds only
+    /// have the shader baked in, and this does nothing.
+    pub fn reload_shader(&mut self) {
+        #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+        {
+            let Ok(modified) = std::fs::metadata(SHADER_PATH).and_then(|meta| meta.modified())
+            else {
+                return;
+            };
+            let first_check = self.shader_watch.modified.is_none();
+            if self.shader_watch.modified.replace(modified) == Some(modified) {
+                return;
+            }
+            let source = match std::fs::read_to_string(SHADER_PATH) {
+                Ok(source) => source,
+                Err(err) => {
+                    eprintln!("couldn't read {SHADER_PATH}: {err}");
+                    return;
+                }
+            };
+
+            let shader = match compile_shader(&self.device, &source) {
+                Ok(shader) => shader,
+                Err(err) => {
+                    eprintln!("{SHADER_PATH} failed to compile: {err}");
+                    return;
+                }
+            };
+            // Entry points that don't match the pipelines only fail here.
+            let pipelines = catch_validation(&self.device, || {
+                create_pipelines(
+                    &self.device,
+                    &shader,
+                    &self.bind_group_layout,
+                    self.surface_config.format,
+                    self.shader_watch.blend,
+                )
+            });
+            match pipelines {
+                Ok(pipelines) => {
+                    [self.pipeline, self.particle_pipeline, self.quiver_pipeline] = pipelines;
+                    if !first_check {
+                        println!("reloaded {SHADER_PATH}");
+                    }
+                }
+                Err(err) => eprintln!("{SHADER_PATH} failed to build its pipelines: {err}"),
+            }
+        }
+    }
+
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        // wgpu panics when configuring a zero-sized surface, which happens
+        // while the window is minimized.
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
+        self.surface_config.width = new_size.width;
+        self.surface_config.height = new_size.height;
+        self.surface.configure(&self.device, &self.surface_config);
+    }
+
+    /// Switches to `present_mode`, or `Fifo` if the surface doesn't support
+    /// it.
+    pub fn set_present_mode(&mut self, present_mode: PresentMode) {
+        let capabilities = self.surface.get_capabilities(&self.adapter);
+        self.surface_config.present_mode = supported_present_mode(&capabilities, present_mode);
+        self.surface.configure(&self.device, &self.surface_config);
+    }
+
+    pub fn step_fluid(&mut self, fluid: &mut Fluid, delta: Duration) {
+        match &mut self.gpu_pressure {
+            Some(gpu) => fluid.step_with(delta, &mut gpu.backend(&self.device, &self.queue)),
+            None => fluid.step(delta),
+        }
+    }
+
+    pub fn advance_fluid(&mut self, fluid: &mut Fluid, real_dt: Duration) -> usize {
+        match &mut self.gpu_pressure {
+            Some(gpu) => fluid.advance_with(real_dt, &mut gpu.backend(&self.device, &self.queue)),
+            None => fluid.advance(real_dt),
+        }
+    }
+
     pub fn render(&self, fluid: &FluidTexture) -> Result<()> {
-        let output = self.surface.get_current_texture()?;
+        let output = match self.surface.get_current_texture() {
+            Ok(output) => output,
+            Err(SurfaceError::Lost | SurfaceError::Outdated) => {
+                self.surface.configure(&self.device, &self.surface_config);
+                return Ok(());
+            }
+            Err(SurfaceError::Timeout) => return Ok(()),
+            Err(err @ SurfaceError::OutOfMemory) => return Err(err.into()),
+        };
         let output_view = output.texture.create_view(&Default::default());
 
         let mut encoder = self.device.create_command_encoder(&Default::default());
+        self.draw(&mut encoder, &output_view, fluid);
+        self.queue.submit(iter::once(encoder.finish()));
+        output.present();
 
+        Ok(())
+    }
+
+    /// Renders `fluid` the way `render` does, but into an offscreen texture
+    /// the size of the window, and reads it back.
+    #[cfg(feature = "image")]
+    pub fn screenshot(&self, fluid: &FluidTexture) -> Result<image::RgbaImage> {
+        let (width, height) = (self.surface_config.width, self.surface_config.height);
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = self.device.create_texture(&TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.surface_config.format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let swap_red_blue = match self.surface_config.format {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+            format => eyre::bail!("can't read back a {format:?} surface"),
+        };
+
+        // Buffer copies need rows padded to a multiple of 256 bytes.
+        let row_bytes = width * mem::size_of::<[u8; 4]>() as u32;
+        let padded_row_bytes =
+            row_bytes.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT) * COPY_BYTES_PER_ROW_ALIGNMENT;
+        let readback = self.device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: (padded_row_bytes * height) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        self.draw(
+            &mut encoder,
+            &texture.create_view(&Default::default()),
+            fluid,
+        );
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &readback,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(height),
+                },
+            },
+            size,
+        );
+        self.queue.submit(iter::once(encoder.finish()));
+
+        let slice = readback.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(Maintain::Wait);
+        receiver.try_recv().map_err(|_| ReadbackPending)??;
+
+        let data = slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((row_bytes * height) as usize);
+        for row in data.chunks_exact(padded_row_bytes as usize) {
+            pixels.extend_from_slice(&row[..row_bytes as usize]);
+        }
+        drop(data);
+        readback.unmap();
+        if swap_red_blue {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        Ok(image::RgbaImage::from_raw(width, height, pixels).unwrap())
+    }
+
+    fn draw(&self, encoder: &mut CommandEncoder, view: &TextureView, fluid: &FluidTexture) {
         let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
             color_attachments: &[Some(RenderPassColorAttachment {
-                view: &output_view,
+                view,
                 resolve_target: None,
                 ops: Operations {
-                    load: wgpu::LoadOp::Clear(Default::default()),
+                    load: wgpu::LoadOp::Clear(self.clear_color),
                     store: true,
                 },
             })],
@@ -263,12 +1253,16 @@ impl Renderer {
         render_pass.set_vertex_buffer(0, self.quad.slice(..));
         render_pass.draw(0..6, 0..1);
 
-        drop(render_pass);
-
-        self.queue.submit(iter::once(encoder.finish()));
-        output.present();
-
-        Ok(())
+        for (pipeline, (buffer, count)) in [
+            (&self.quiver_pipeline, &fluid.quiver_vertices),
+            (&self.particle_pipeline, &fluid.particle_vertices),
+        ] {
+            if let Some(buffer) = buffer.as_ref().filter(|_| *count > 0) {
+                render_pass.set_pipeline(pipeline);
+                render_pass.set_vertex_buffer(0, buffer.slice(..));
+                render_pass.draw(0..*count, 0..1);
+            }
+        }
     }
 }
 
@@ -282,3 +1276,120 @@ impl Display for NoAdapter {
 }
 
 impl Error for NoAdapter {}
+
+/// The adapter reports no `missing` capabilities for the window's surface,
+/// so nothing can be presented to it.
+#[derive(Debug, Clone, Copy)]
+pub struct UnsupportedSurface {
+    pub missing: &'static str,
+}
+
+impl Display for UnsupportedSurface {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the surface supports no {}", self.missing)
+    }
+}
+
+impl Error for UnsupportedSurface {}
+
+/// The GPU didn't finish a readback when polled, which happens on the web
+/// where `Device::poll` can't block.
+#[cfg(feature = "image")]
+#[derive(Debug, Clone, Copy)]
+pub struct ReadbackPending;
+
+#[cfg(feature = "image")]
+impl Display for ReadbackPending {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the GPU readback did not complete")
+    }
+}
+
+#[cfg(feature = "image")]
+impl Error for ReadbackPending {}
+
+#[cfg(all(test, debug_assertions, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    const FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+    /// A device without a window, or `None` on machines with no adapter.
+    fn device() -> Option<(Device, Queue)> {
+        let instance = Instance::default();
+        let adapter = futures::executor::block_on(instance.request_adapter(&Default::default()))?;
+        let limits = Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits());
+        futures::executor::block_on(adapter.request_device(
+            &DeviceDescriptor {
+                label: None,
+                features: Features::empty(),
+                limits,
+            },
+            None,
+        ))
+        .ok()
+    }
+
+    #[test]
+    fn broken_shader_keeps_pipelines() {
+        let Some((device, queue)) = device() else {
+            eprintln!("no adapter, skipping");
+            return;
+        };
+        device.on_uncaptured_error(Box::new(|err| panic!("uncaptured: {err}")));
+        let layout = create_bind_group_layout(&device);
+        let build_pipelines = |shader: &ShaderModule| {
+            catch_validation(&device, || {
+                create_pipelines(&device, shader, &layout, FORMAT, BlendState::REPLACE)
+            })
+        };
+        let shader = compile_shader(&device, include_str!("shader.wgsl")).unwrap();
+        let pipelines = build_pipelines(&shader).unwrap();
+
+        assert!(compile_shader(&device, "fn broken( {").is_err());
+        // Valid WGSL, but without the entry points the pipelines use.
+        let unrelated = compile_shader(
+            &device,
+            "@fragment fn other() -> @location(0) vec4<f32> { return vec4(1.0); }",
+        )
+        .unwrap();
+        assert!(build_pipelines(&unrelated).is_err());
+
+        // The pipelines from before still record without errors.
+        let target = device.create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width: 4,
+                height: 4,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = target.create_view(&Default::default());
+        let recorded = catch_validation(&device, || {
+            let mut encoder = device.create_command_encoder(&Default::default());
+            for pipeline in &pipelines {
+                let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                    label: None,
+                    color_attachments: &[Some(RenderPassColorAttachment {
+                        view: &view,
+                        resolve_target: None,
+                        ops: Operations {
+                            load: LoadOp::Clear(Color::BLACK),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                render_pass.set_pipeline(pipeline);
+            }
+            queue.submit(iter::once(encoder.finish()));
+        });
+        assert!(recorded.is_ok(), "{recorded:?}");
+    }
+}
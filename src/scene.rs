@@ -0,0 +1,212 @@
+use std::time::Duration;
+
+use ndarray::Array2;
+
+use crate::fluid::{
+    Boundary, BoundaryMode, Cell, CpuPressure, Edge, Emitter, Falloff, Fluid, PressureBackend,
+    Real, Vec2, Vec3,
+};
+
+const TAU: Real = std::f64::consts::TAU as Real;
+const SHEAR_SPEED: Real = 0.25;
+const SHEAR_THICKNESS: Real = 0.02;
+const SHEAR_WAVES: Real = 4.0;
+const SHEAR_PERTURBATION: Real = 0.01;
+const VORTEX_RADIUS: Real = 0.08;
+const VORTEX_CIRCULATION: Real = 0.4;
+const CYLINDER_FLOW_SPEED: Real = 0.3;
+const CYLINDER_CENTER: Vec2 = Vec2::new(0.3, 0.5);
+const CYLINDER_RADIUS: Real = 0.08;
+
+/// A fluid set up for a scenario. The emitters, velocity regions, obstacles
+/// and boundaries all live on `fluid`; a scene builds them up in one place,
+/// steps with a single `update`, and remembers the starting flow so `reset`
+/// can replay the scenario.
+pub struct Scene {
+    pub fluid: Fluid,
+    initial: Array2<Cell>,
+}
+
+impl Scene {
+    pub fn new(fluid: Fluid) -> Self {
+        Self {
+            initial: fluid.cells.current().clone(),
+            fluid,
+        }
+    }
+
+    pub fn emitter(mut self, emitter: Emitter) -> Self {
+        self.fluid.add_emitter(emitter);
+        self
+    }
+
+    pub fn velocity_region(
+        mut self,
+        min: (isize, isize),
+        max: (isize, isize),
+        velocity: Vec2,
+    ) -> Self {
+        self.fluid.add_velocity_region(min, max, velocity);
+        self
+    }
+
+    /// Fills the cells from `min` to `max` inclusive with obstacles.
+    pub fn obstacle_rect(mut self, min: (isize, isize), max: (isize, isize)) -> Self {
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                self.fluid.set_obstacle(x, y, true);
+            }
+        }
+        self.initial = self.fluid.cells.current().clone();
+        self
+    }
+
+    pub fn boundary(mut self, boundary: impl Into<Boundary>) -> Self {
+        self.fluid.boundary = boundary.into();
+        self
+    }
+
+    /// Applies the sources and steps the fluid by `delta`, in fixed steps if
+    /// the fluid has a `fixed_dt`. Returns the number of steps taken.
+    pub fn update(&mut self, delta: Duration) -> usize {
+        self.update_with(delta, &mut CpuPressure)
+    }
+
+    /// Like `update`, but solves for pressure with `backend`.
+    pub fn update_with(&mut self, delta: Duration, backend: &mut dyn PressureBackend) -> usize {
+        if self.fluid.fixed_dt.is_zero() {
+            self.fluid.step_with(delta, backend);
+            1
+        } else {
+            self.fluid.advance_with(delta, backend)
+        }
+    }
+
+    /// Puts the flow back to how it was when the scene was built, keeping
+    /// its sources and obstacles.
+    pub fn reset(&mut self) {
+        self.fluid.clear();
+        self.fluid.cells.set(self.initial.clone());
+    }
+}
+
+/// Ready-made scenes that show off the solver. Positions and sizes are
+/// relative to the grid, so they work at any resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scenario {
+    /// Hot smoke rising from a source at the bottom of a closed box.
+    Plume,
+    /// Two layers sliding past each other, rolling up into Kelvin-Helmholtz
+    /// billows.
+    ShearLayer,
+    /// Two vortices spinning opposite ways, which carry each other along.
+    VortexPair,
+    /// A steady stream from the left past a round obstacle.
+    Cylinder,
+}
+
+impl Scenario {
+    pub const ALL: [Scenario; 4] = [
+        Scenario::Plume,
+        Scenario::ShearLayer,
+        Scenario::VortexPair,
+        Scenario::Cylinder,
+    ];
+
+    /// Clears `scene`'s flow, sources, obstacles and forces, then sets up
+    /// the scenario. The grid size, diffusion and viscosity stay as they
+    /// were. `Scene::reset` afterwards replays the scenario.
+    pub fn setup(self, scene: &mut Scene) {
+        let fluid = &mut scene.fluid;
+        fluid.clear();
+        fluid.clear_emitters();
+        fluid.clear_velocity_regions();
+        fluid.inflows.clear();
+        fluid.obstacles.fill(false);
+        fluid.set_ambient_flow(None);
+        fluid.gravity = Vec2::ZERO;
+        fluid.buoyancy_alpha = 0.0;
+        fluid.buoyancy_beta = 0.0;
+        fluid.vorticity = 0.0;
+
+        match self {
+            Scenario::Plume => {
+                fluid.boundary = BoundaryMode::Reflect.into();
+                fluid.buoyancy_beta = 1.0;
+                fluid.add_emitter(Emitter {
+                    position: Vec2::new(0.5, 0.1),
+                    radius: 0.04,
+                    density_rate: 4.0,
+                    temperature_rate: 4.0,
+                    velocity: Vec2::new(0.0, 0.1),
+                });
+            }
+            Scenario::ShearLayer => {
+                fluid.boundary = Boundary::new(BoundaryMode::Wrap, BoundaryMode::Reflect);
+                fluid.init_with(|x, y| {
+                    // Smooth the step between the layers over a few cells
+                    // and nudge it with a sine so it starts to roll up.
+                    let layer = ((y - 0.5) / SHEAR_THICKNESS).tanh();
+                    let wave = (TAU * SHEAR_WAVES * x).sin();
+                    Cell {
+                        dye: Vec3::new(1.0, 0.3, 0.1)
+                            .lerp(Vec3::new(0.1, 0.6, 1.0), layer * 0.5 + 0.5),
+                        velocity: Vec2::new(SHEAR_SPEED * layer, SHEAR_PERTURBATION * wave),
+                        temperature: 0.0,
+                    }
+                });
+            }
+            Scenario::VortexPair => {
+                fluid.boundary = BoundaryMode::Wrap.into();
+                let vortices = [
+                    (Vec2::new(0.4, 0.5), 1.0, Vec3::new(1.0, 0.3, 0.1)),
+                    (Vec2::new(0.6, 0.5), -1.0, Vec3::new(0.1, 0.6, 1.0)),
+                ];
+                fluid.init_with(|x, y| {
+                    let mut cell = Cell::default();
+                    for (center, spin, color) in vortices {
+                        let offset = Vec2::new(x, y) - center;
+                        cell.dye +=
+                            color * Falloff::Gaussian.weight(offset.length() / VORTEX_RADIUS);
+                        // Lamb-Oseen: a smooth core with the far field of a
+                        // point vortex, so each vortex moves the other.
+                        let distance_squared = offset.length_squared().max(Real::EPSILON);
+                        let core =
+                            1.0 - (-distance_squared / (VORTEX_RADIUS * VORTEX_RADIUS)).exp();
+                        cell.velocity += offset.perp() / distance_squared
+                            * (spin * VORTEX_CIRCULATION / TAU * core);
+                    }
+                    cell
+                });
+            }
+            Scenario::Cylinder => {
+                fluid.boundary = Boundary::new(BoundaryMode::Open, BoundaryMode::Reflect);
+                fluid.vorticity = 0.5;
+                let velocity = Vec2::new(CYLINDER_FLOW_SPEED, 0.0);
+                fluid.set_inflow(Edge::Left, velocity, 0.0);
+                for y in [0.35, 0.45, 0.55, 0.65] {
+                    fluid.add_emitter(Emitter {
+                        position: Vec2::new(0.05, y),
+                        radius: 0.015,
+                        density_rate: 8.0,
+                        temperature_rate: 0.0,
+                        velocity,
+                    });
+                }
+
+                let (width, height) = (fluid.width, fluid.height);
+                let size = Vec2::new(width as Real, height as Real);
+                for x in 0..width {
+                    for y in 0..height {
+                        let position = (Vec2::new(x as Real, y as Real) + 0.5) / size;
+                        if position.distance(CYLINDER_CENTER) < CYLINDER_RADIUS {
+                            fluid.set_obstacle(x as isize, y as isize, true);
+                        }
+                    }
+                }
+            }
+        }
+
+        scene.initial = scene.fluid.cells.current().clone();
+    }
+}
@@ -3,22 +3,110 @@ struct Fragment {
     @location(0) tex_coords: vec2<f32>,
 }
 
+struct Params {
+    mode: u32,
+    use_colormap: u32,
+    density_alpha: u32,
+    // Fluid position at the window center, then zoom.
+    camera: vec4<f32>,
+}
+
+const MODE_DENSITY: u32 = 0u;
+const MODE_VELOCITY: u32 = 1u;
+const MODE_PRESSURE: u32 = 2u;
+const MODE_CURL: u32 = 3u;
+const MODE_DIVERGENCE: u32 = 4u;
+const MODE_SPEED_TINT: u32 = 5u;
+
 @group(0) @binding(0)
 var fluid_texture: texture_2d<f32>;
 @group(0) @binding(1)
 var fluid_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> params: Params;
+@group(0) @binding(3)
+var colormap_texture: texture_2d<f32>;
+
+// `position` runs from -1 to 1 across the fluid.
+fn to_clip(position: vec2<f32>) -> vec4<f32> {
+    return vec4((position - params.camera.xy) * params.camera.z, 0.0, 1.0);
+}
 
 @vertex
 fn vs_main(@location(0) position: vec2<f32>) -> Fragment {
     var fragment: Fragment;
-    fragment.clip_position = vec4(position, 0.0, 1.0);
+    fragment.clip_position = to_clip(position);
     fragment.tex_coords = position / 2.0 + 0.5;
     return fragment;
 }
 
+// Particles are in normalized fluid coordinates from 0 to 1.
+@vertex
+fn vs_particle(@location(0) position: vec2<f32>) -> @builtin(position) vec4<f32> {
+    return to_clip(position * 2.0 - 1.0);
+}
+
+@fragment
+fn fs_particle() -> @location(0) vec4<f32> {
+    return vec4(1.0, 1.0, 1.0, 1.0);
+}
+
+struct QuiverVertex {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+}
+
+// Arrow vertices are in normalized fluid coordinates like particles.
+@vertex
+fn vs_quiver(@location(0) position: vec2<f32>, @location(1) color: vec3<f32>) -> QuiverVertex {
+    var vertex: QuiverVertex;
+    vertex.clip_position = to_clip(position * 2.0 - 1.0);
+    vertex.color = color;
+    return vertex;
+}
+
+@fragment
+fn fs_quiver(vertex: QuiverVertex) -> @location(0) vec4<f32> {
+    return vec4(pow(vertex.color, vec3(2.2)), 1.0);
+}
+
+fn hue_to_rgb(hue: f32) -> vec3<f32> {
+    let k = vec3(5.0, 3.0, 1.0);
+    let p = abs(fract(hue + k / 6.0) * 6.0 - 3.0);
+    return clamp(p - 1.0, vec3(0.0), vec3(1.0));
+}
+
+fn velocity_color(sample: vec4<f32>) -> vec3<f32> {
+    let direction = sample.xy * 2.0 - 1.0;
+    let hue = atan2(direction.y, direction.x) / 6.28318530718 + 0.5;
+    return hue_to_rgb(hue) * sample.z;
+}
+
+// Red for positive values, blue for negative.
+fn diverging_color(sample: vec4<f32>) -> vec3<f32> {
+    let value = sample.x * 2.0 - 1.0;
+    return vec3(max(value, 0.0), 0.0, max(-value, 0.0));
+}
+
 @fragment
 fn fs_main(fragment: Fragment) -> @location(0) vec4<f32> {
-    let density = textureSample(fluid_texture, fluid_sampler, fragment.tex_coords).x;
-    let color = pow(density, 2.2);
-    return vec4(color, color, color, 1.0);
+    let sample = textureSample(fluid_texture, fluid_sampler, fragment.tex_coords);
+    var color = sample.rgb;
+    if params.use_colormap != 0u {
+        // The sampler may repeat to match a wrapping fluid; keep the lookup
+        // inside the first and last texel centers so the LUT never wraps.
+        let half_texel = 0.5 / f32(textureDimensions(colormap_texture).x);
+        let t = clamp(sample.a, half_texel, 1.0 - half_texel);
+        color = textureSampleLevel(colormap_texture, fluid_sampler, vec2(t, 0.5), 0.0).rgb;
+    }
+    if params.mode == MODE_VELOCITY {
+        color = velocity_color(sample);
+    } else if params.mode >= MODE_PRESSURE && params.mode <= MODE_DIVERGENCE {
+        color = diverging_color(sample);
+    }
+    var alpha = 1.0;
+    if params.density_alpha != 0u && (params.mode == MODE_DENSITY || params.mode == MODE_SPEED_TINT) {
+        alpha = sample.a;
+    }
+    return vec4(pow(color, vec3(2.2)), alpha);
 }
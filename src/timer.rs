@@ -1,13 +1,27 @@
-use std::{time::{Instant, Duration}, collections::VecDeque};
+use std::{time::Duration, collections::VecDeque};
+
+// `std::time::Instant` panics in the browser.
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+pub use web_time::Instant;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Timer {
     pub last_tick: Instant,
+    /// When set, `delta` reports this instead of the time since the last
+    /// tick, so a run doesn't depend on how fast frames come.
+    pub fixed_delta: Option<Duration>,
 }
 
 impl Timer {
     pub fn new() -> Self {
-        Self { last_tick: Instant::now() }
+        Self { last_tick: Instant::now(), fixed_delta: None }
+    }
+
+    /// A manual clock that advances by exactly `delta` every frame.
+    pub fn fixed(delta: Duration) -> Self {
+        Self { fixed_delta: Some(delta), ..Self::new() }
     }
 
     pub fn tick(&mut self) {
@@ -15,12 +29,21 @@ impl Timer {
     }
 
     pub fn delta(&self) -> Duration {
-        Instant::now() - self.last_tick
+        self.fixed_delta.unwrap_or_else(|| Instant::now() - self.last_tick)
     }
 }
 
-#[derive(Debug, Clone, Default)]
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const DEFAULT_FPS_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
 pub struct FpsCounter {
+    pub window: Duration,
     frames: VecDeque<Instant>,
 }
 
@@ -29,19 +52,142 @@ impl FpsCounter {
         Default::default()
     }
 
+    pub fn with_window(window: Duration) -> Self {
+        Self {
+            window,
+            frames: VecDeque::new(),
+        }
+    }
+
     pub fn add_frame(&mut self) {
-        let now = Instant::now();
-        self.frames.push_back(now);
+        self.add_frame_at(Instant::now());
+    }
+
+    pub fn add_frame_at(&mut self, time: Instant) {
+        self.frames.push_back(time);
+    }
+
+    pub fn fps(&mut self) -> f32 {
+        self.fps_at(Instant::now())
     }
 
-    pub fn fps(&mut self) -> usize {
-        let now = Instant::now();
+    /// Frames per second over the `window` ending at `now`. A frame exactly
+    /// `window` old has left the window.
+    pub fn fps_at(&mut self, now: Instant) -> f32 {
         while let Some(&first) = self.frames.front() {
-            if first + Duration::new(1, 0) >= now {
+            if now.saturating_duration_since(first) < self.window {
                 break;
             }
             self.frames.pop_front();
         }
+        self.frames.len() as f32 / self.window.as_secs_f32()
+    }
+}
+
+impl Default for FpsCounter {
+    fn default() -> Self {
+        Self::with_window(DEFAULT_FPS_WINDOW)
+    }
+}
+
+/// Rolling statistics over the last `capacity` frame durations.
+#[derive(Debug, Clone)]
+pub struct FrameStats {
+    capacity: usize,
+    frames: VecDeque<Duration>,
+}
+
+impl FrameStats {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            frames: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, frame_time: Duration) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame_time);
+    }
+
+    pub fn len(&self) -> usize {
         self.frames.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn avg(&self) -> Option<Duration> {
+        if self.frames.is_empty() {
+            return None;
+        }
+        let total: Duration = self.frames.iter().sum();
+        Some(total / self.frames.len() as u32)
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.frames.iter().copied().min()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.frames.iter().copied().max()
+    }
+
+    /// Nearest-rank percentile, with `p` in `0.0..=100.0`.
+    pub fn percentile(&self, p: f32) -> Option<Duration> {
+        let mut sorted: Vec<_> = self.frames.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = (p.clamp(0.0, 100.0) / 100.0 * sorted.len() as f32).ceil() as usize;
+        sorted.get(rank.saturating_sub(1)).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `frames` frames evenly spaced `interval` apart, from `start`.
+    fn counter_with_frames(
+        window: Duration,
+        start: Instant,
+        frames: u32,
+        interval: Duration,
+    ) -> FpsCounter {
+        let mut counter = FpsCounter::with_window(window);
+        for i in 0..frames {
+            counter.add_frame_at(start + interval * i);
+        }
+        counter
+    }
+
+    #[test]
+    fn fps_counts_frames_in_window() {
+        let start = Instant::now();
+        let interval = Duration::from_millis(10);
+        let mut counter = counter_with_frames(Duration::from_millis(500), start, 50, interval);
+        assert_eq!(counter.fps_at(start + interval * 49), 100.0);
+    }
+
+    #[test]
+    fn fps_evicts_frame_exactly_window_old() {
+        let start = Instant::now();
+        let window = Duration::from_secs(1);
+        let mut counter = counter_with_frames(window, start, 2, Duration::from_millis(500));
+        assert_eq!(counter.fps_at(start + window - Duration::from_millis(1)), 2.0);
+        assert_eq!(counter.fps_at(start + window), 1.0);
+    }
+
+    #[test]
+    fn fps_scales_by_window_length() {
+        let start = Instant::now();
+        let interval = Duration::from_millis(20);
+        let mut counter = counter_with_frames(Duration::from_secs(2), start, 100, interval);
+        assert_eq!(counter.fps_at(start + interval * 99), 50.0);
+    }
 }
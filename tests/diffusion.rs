@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use fluidsim::fluid::{BoundaryMode, Cell, Fluid, Real, Vec3};
+
+const SIZE: usize = 64;
+const DIFFUSION: Real = 1e-4;
+const SIGMA: Real = 3.0;
+const STEPS: usize = 60;
+const DELTA: Duration = Duration::from_millis(16);
+
+/// Peak-normalized Gaussian of variance `variance` around the grid center,
+/// with `x` and `y` in cells. Its weight past half the grid is negligible,
+/// so periodic images are left out.
+fn gaussian(x: Real, y: Real, variance: Real) -> Real {
+    let center = SIZE as Real / 2.0;
+    let distance_squared = (x - center).powi(2) + (y - center).powi(2);
+    (-distance_squared / (2.0 * variance)).exp()
+}
+
+#[test]
+fn diffusion_matches_analytic_gaussian() {
+    let mut fluid = Fluid::builder()
+        .size(SIZE)
+        .diffusion(DIFFUSION)
+        .boundary(BoundaryMode::Wrap)
+        .build();
+    let size = SIZE as Real;
+    fluid.init_with(|x, y| Cell {
+        dye: Vec3::splat(gaussian(x * size - 0.5, y * size - 0.5, SIGMA * SIGMA)),
+        ..Cell::default()
+    });
+    let initial_density = fluid.total_density();
+
+    fluid.step_n(DELTA, STEPS);
+
+    // `diffusion` is per unit domain, so in cells it scales by the size
+    // squared. The variance grows by `2 D t`, and the peak falls to keep the
+    // total constant.
+    let time = STEPS as Real * DELTA.as_secs_f64() as Real;
+    let variance = SIGMA * SIGMA + 2.0 * DIFFUSION * size * size * time;
+    let peak = SIGMA * SIGMA / variance;
+    let max_error = fluid
+        .iter()
+        .map(|((x, y), cell)| {
+            let expected = peak * gaussian(x as Real, y as Real, variance);
+            (cell.density() - expected).abs()
+        })
+        .fold(0.0, Real::max);
+    // The implicit solve lands within about 0.2% of the peak; leave margin.
+    assert!(
+        max_error < 0.005 * peak,
+        "max error {max_error} against peak {peak}"
+    );
+
+    let total_density = fluid.total_density();
+    assert!(
+        (total_density - initial_density).abs() < 1e-4 * initial_density,
+        "total dye went from {initial_density} to {total_density}"
+    );
+}